@@ -0,0 +1,714 @@
+use crate::chip8::{SymbolTable, PROGRAM_START};
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Highest address CHIP-8 programs can reach, with the XO-CHIP 64K memory
+const MAX_ADDRESS: i64 = 0xFFFF;
+
+/// Deepest chain of includes, guarding against include cycles
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Error found while assembling, along with where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    /// None when assembling a string with assemble()
+    pub file: Option<PathBuf>,
+    /// 1 based, 0 for errors about the whole program
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = match &self.file {
+            Some(file) => file.display().to_string(),
+            None => "<source>".to_string(),
+        };
+
+        match self.line {
+            0 => write!(f, "{}: {}", file, self.message),
+            line => write!(f, "{}:{}: {}", file, line, self.message),
+        }
+    }
+}
+
+impl Error for AssemblyError {}
+
+/// An assembled program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// Bytes to load at origin
+    pub rom: Vec<u8>,
+    pub origin: usize,
+    /// Address of each label
+    pub labels: BTreeMap<String, usize>,
+}
+
+impl Assembly {
+    /// One '0xADDR label' line per label, in address order
+    pub fn symbol_file(&self) -> String {
+        let mut labels: Vec<(&usize, &String)> = self
+            .labels
+            .iter()
+            .map(|(name, address)| (address, name))
+            .collect();
+        labels.sort();
+
+        labels
+            .iter()
+            .map(|(address, name)| format!("{:#06x} {}\n", address, name))
+            .collect()
+    }
+
+    /// Labels for the debugger and disassembly
+    pub fn symbols(&self) -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        for (name, &address) in &self.labels {
+            symbols.insert(name, address);
+        }
+        symbols
+    }
+}
+
+/// Assembles source, includes being relative to the current directory
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AssemblyError>> {
+    let mut assembler = Assembler::new();
+    assembler.read_source(source, None, 0);
+    assembler.finish()
+}
+
+/// Assembles the file at path, includes being relative to the including file
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Assembly, Vec<AssemblyError>> {
+    let mut assembler = Assembler::new();
+    assembler.read_file(path.as_ref(), None, 0);
+    assembler.finish()
+}
+
+/// Where a statement comes from
+#[derive(Debug, Clone)]
+struct Location {
+    file: Option<PathBuf>,
+    line: usize,
+}
+
+enum StatementKind {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+/// Statement emitting bytes, encoded once every label is known
+struct Statement {
+    location: Location,
+    address: usize,
+    kind: StatementKind,
+}
+
+struct Assembler {
+    origin: usize,
+    address: usize,
+    statements: Vec<Statement>,
+    symbols: HashMap<String, i64>,
+    labels: BTreeMap<String, usize>,
+    errors: Vec<AssemblyError>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            origin: PROGRAM_START,
+            address: PROGRAM_START,
+            statements: Vec::new(),
+            symbols: HashMap::new(),
+            labels: BTreeMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, location: &Location, message: String) {
+        self.errors.push(AssemblyError {
+            file: location.file.clone(),
+            line: location.line,
+            message,
+        });
+    }
+
+    fn read_file(&mut self, path: &Path, included_from: Option<&Location>, depth: usize) {
+        match fs::read_to_string(path) {
+            Ok(source) => self.read_source(&source, Some(path.to_path_buf()), depth),
+            Err(error) => {
+                let location = included_from.cloned().unwrap_or(Location {
+                    file: Some(path.to_path_buf()),
+                    line: 0,
+                });
+                self.error(
+                    &location,
+                    format!("can't read {}: {}", path.display(), error),
+                );
+            }
+        }
+    }
+
+    /// First pass: defines labels and constants, and lays out the statements
+    fn read_source(&mut self, source: &str, file: Option<PathBuf>, depth: usize) {
+        for (index, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.clone(),
+                line: index + 1,
+            };
+            if let Err(message) = self.read_line(line, &location, depth) {
+                self.error(&location, message);
+            }
+        }
+    }
+
+    fn read_line(&mut self, line: &str, location: &Location, depth: usize) -> Result<(), String> {
+        let mut line = strip_comment(line).trim();
+
+        if let Some(colon) = line.find(':') {
+            let name = &line[..colon];
+            if is_symbol(name) {
+                self.define_label(name)?;
+                line = line[colon + 1..].trim();
+            }
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (head, rest) = split_first_word(line);
+        let (second, value) = split_first_word(rest);
+        if second.eq_ignore_ascii_case("equ") || second == "=" {
+            return self.define_constant(head, value);
+        }
+
+        match head.to_lowercase().as_str() {
+            "db" | ".byte" => {
+                let bytes = split_operands(rest);
+                self.push(location, bytes.len(), StatementKind::Bytes(bytes))
+            }
+            "dw" | ".word" => {
+                let words = split_operands(rest);
+                self.push(location, 2 * words.len(), StatementKind::Words(words))
+            }
+            "org" => {
+                let address = evaluate(rest, &self.symbols)?;
+                if address > MAX_ADDRESS {
+                    return Err(format!(
+                        "org {:#06x} is past the end of memory {:#06x}",
+                        address, MAX_ADDRESS
+                    ));
+                }
+                if address < self.address as i64 {
+                    return Err(format!(
+                        "org {:#06x} is before the current address {:#06x}",
+                        address, self.address
+                    ));
+                }
+                if self.statements.is_empty() {
+                    self.origin = address as usize;
+                }
+                self.address = address as usize;
+                Ok(())
+            }
+            "include" => {
+                let path = rest
+                    .strip_prefix('"')
+                    .and_then(|path| path.strip_suffix('"'))
+                    .ok_or("expected include \"FILE\"")?;
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err("includes nested too deep, is a file including itself?".into());
+                }
+
+                let path = match location.file.as_ref().and_then(|file| file.parent()) {
+                    Some(directory) => directory.join(path),
+                    None => PathBuf::from(path),
+                };
+                self.read_file(&path, Some(location), depth + 1);
+                Ok(())
+            }
+            _ => {
+                let operands = split_operands(rest);
+                let size = match operands.get(1) {
+                    Some(operand) if is_long(operand) => 4,
+                    _ => 2,
+                };
+                let instruction = StatementKind::Instruction {
+                    mnemonic: head.to_uppercase(),
+                    operands,
+                };
+                self.push(location, size, instruction)
+            }
+        }
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        self.define_constant(name, &self.address.to_string())?;
+        self.labels.insert(name.to_string(), self.address);
+
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if !is_symbol(name) {
+            return Err(format!("invalid symbol name '{}'", name));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(format!("'{}' is already defined", name));
+        }
+
+        let value = evaluate(value, &self.symbols)?;
+        self.symbols.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    fn push(
+        &mut self,
+        location: &Location,
+        size: usize,
+        kind: StatementKind,
+    ) -> Result<(), String> {
+        if self.address + size > MAX_ADDRESS as usize + 1 {
+            return Err("program goes past the end of memory".to_string());
+        }
+
+        self.statements.push(Statement {
+            location: location.clone(),
+            address: self.address,
+            kind,
+        });
+        self.address += size;
+        Ok(())
+    }
+
+    /// Second pass: encodes the statements
+    fn finish(mut self) -> Result<Assembly, Vec<AssemblyError>> {
+        let mut rom = vec![0; self.address.saturating_sub(self.origin)];
+
+        for statement in std::mem::take(&mut self.statements) {
+            match encode(&statement.kind, &self.symbols) {
+                Ok(bytes) => {
+                    let offset = statement.address - self.origin;
+                    rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                }
+                Err(message) => self.error(&statement.location, message),
+            }
+        }
+
+        if rom.is_empty() && self.errors.is_empty() {
+            self.errors.push(AssemblyError {
+                file: None,
+                line: 0,
+                message: "no code or data to assemble".to_string(),
+            });
+        }
+
+        if self.errors.is_empty() {
+            Ok(Assembly {
+                rom,
+                origin: self.origin,
+                labels: self.labels,
+            })
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Parsed instruction operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    /// VX - VY, for the XO-CHIP 5XY2 and 5XY3
+    Range(u8, u8),
+    I,
+    /// [I]
+    Memory,
+    Delay,
+    Sound,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(i64),
+    Value(i64),
+}
+
+fn parse_operand(text: &str, symbols: &HashMap<String, i64>) -> Result<Operand, String> {
+    if let Some(x) = parse_register(text) {
+        return Ok(Operand::Register(x));
+    }
+    if let Some((x, y)) = text.split_once('-') {
+        if let (Some(x), Some(y)) = (parse_register(x.trim()), parse_register(y.trim())) {
+            return Ok(Operand::Range(x, y));
+        }
+    }
+    if is_long(text) {
+        return Ok(Operand::Long(evaluate(&text[4..], symbols)?));
+    }
+
+    let operand = match text.to_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::Memory,
+        "DT" => Operand::Delay,
+        "ST" => Operand::Sound,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::BigFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        _ => Operand::Value(evaluate(text, symbols)?),
+    };
+    Ok(operand)
+}
+
+fn encode(kind: &StatementKind, symbols: &HashMap<String, i64>) -> Result<Vec<u8>, String> {
+    match kind {
+        StatementKind::Bytes(values) => values
+            .iter()
+            .map(|value| Ok(byte(evaluate(value, symbols)?)? as u8))
+            .collect(),
+        StatementKind::Words(values) => {
+            let mut bytes = Vec::with_capacity(2 * values.len());
+            for value in values {
+                let word = in_range(evaluate(value, symbols)?, 0, 0xFFFF, "word")?;
+                bytes.extend_from_slice(&word.to_be_bytes());
+            }
+            Ok(bytes)
+        }
+        StatementKind::Instruction { mnemonic, operands } => {
+            let operands = operands
+                .iter()
+                .map(|operand| parse_operand(operand, symbols))
+                .collect::<Result<Vec<Operand>, String>>()?;
+
+            if let ("LD", [Operand::I, Operand::Long(address)]) = (mnemonic.as_str(), &operands[..])
+            {
+                let address = in_range(*address, 0, 0xFFFF, "address")?;
+                return Ok(vec![0xF0, 0x00, (address >> 8) as u8, address as u8]);
+            }
+            Ok(encode_instruction(mnemonic, &operands)?
+                .to_be_bytes()
+                .to_vec())
+        }
+    }
+}
+
+fn encode_instruction(mnemonic: &str, operands: &[Operand]) -> Result<u16, String> {
+    use Operand::*;
+
+    let xy = |opcode: u16, x: u8, y: u8| opcode | (x as u16) << 8 | (y as u16) << 4;
+    let x = |opcode: u16, x: u8| opcode | (x as u16) << 8;
+
+    let opcode = match (mnemonic, operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCU", [Value(n)]) => 0x00D0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("SYS", [Value(nnn)]) => address(*nnn)?,
+        ("JP", [Value(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [Register(0), Value(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Value(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [Register(vx), Value(kk)]) => x(0x3000, *vx) | byte(*kk)?,
+        ("SE", [Register(vx), Register(vy)]) => xy(0x5000, *vx, *vy),
+        ("SNE", [Register(vx), Value(kk)]) => x(0x4000, *vx) | byte(*kk)?,
+        ("SNE", [Register(vx), Register(vy)]) => xy(0x9000, *vx, *vy),
+        ("LD", [Register(vx), Value(kk)]) => x(0x6000, *vx) | byte(*kk)?,
+        ("LD", [Register(vx), Register(vy)]) => xy(0x8000, *vx, *vy),
+        ("LD", [I, Value(nnn)]) => 0xA000 | address(*nnn)?,
+        ("LD", [Register(vx), Delay]) => x(0xF007, *vx),
+        ("LD", [Register(vx), Key]) => x(0xF00A, *vx),
+        ("LD", [Delay, Register(vx)]) => x(0xF015, *vx),
+        ("LD", [Sound, Register(vx)]) => x(0xF018, *vx),
+        ("LD", [Font, Register(vx)]) => x(0xF029, *vx),
+        ("LD", [BigFont, Register(vx)]) => x(0xF030, *vx),
+        ("LD", [Bcd, Register(vx)]) => x(0xF033, *vx),
+        ("LD", [Memory, Register(vx)]) => x(0xF055, *vx),
+        ("LD", [Register(vx), Memory]) => x(0xF065, *vx),
+        ("LD", [Flags, Register(vx)]) => x(0xF075, *vx),
+        ("LD", [Register(vx), Flags]) => x(0xF085, *vx),
+        ("LD", [Memory, Range(vx, vy)]) => xy(0x5002, *vx, *vy),
+        ("LD", [Range(vx, vy), Memory]) => xy(0x5003, *vx, *vy),
+        ("ADD", [Register(vx), Value(kk)]) => x(0x7000, *vx) | byte(*kk)?,
+        ("ADD", [Register(vx), Register(vy)]) => xy(0x8004, *vx, *vy),
+        ("ADD", [I, Register(vx)]) => x(0xF01E, *vx),
+        ("OR", [Register(vx), Register(vy)]) => xy(0x8001, *vx, *vy),
+        ("AND", [Register(vx), Register(vy)]) => xy(0x8002, *vx, *vy),
+        ("XOR", [Register(vx), Register(vy)]) => xy(0x8003, *vx, *vy),
+        ("SUB", [Register(vx), Register(vy)]) => xy(0x8005, *vx, *vy),
+        ("SHR", [Register(vx)]) => xy(0x8006, *vx, *vx),
+        ("SHR", [Register(vx), Register(vy)]) => xy(0x8006, *vx, *vy),
+        ("SUBN", [Register(vx), Register(vy)]) => xy(0x8007, *vx, *vy),
+        ("SHL", [Register(vx)]) => xy(0x800E, *vx, *vx),
+        ("SHL", [Register(vx), Register(vy)]) => xy(0x800E, *vx, *vy),
+        ("RND", [Register(vx), Value(kk)]) => x(0xC000, *vx) | byte(*kk)?,
+        ("DRW", [Register(vx), Register(vy), Value(n)]) => xy(0xD000, *vx, *vy) | nibble(*n)?,
+        ("SKP", [Register(vx)]) => x(0xE09E, *vx),
+        ("SKNP", [Register(vx)]) => x(0xE0A1, *vx),
+        ("PLANE", [Value(n)]) => 0xF001 | nibble(*n)? << 8,
+        ("AUDIO", []) => 0xF002,
+        ("PITCH", [Register(vx)]) => x(0xF03A, *vx),
+        _ if KNOWN_MNEMONICS.contains(&mnemonic) => {
+            return Err(format!("invalid operands for {}", mnemonic))
+        }
+        _ => return Err(format!("unknown mnemonic '{}'", mnemonic)),
+    };
+    Ok(opcode)
+}
+
+const KNOWN_MNEMONICS: [&str; 30] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE",
+    "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP",
+    "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn in_range(value: i64, min: i64, max: i64, what: &str) -> Result<u16, String> {
+    if value < min || value > max {
+        Err(format!("{} {} is out of range", what, value))
+    } else {
+        Ok(value as u16)
+    }
+}
+
+fn nibble(value: i64) -> Result<u16, String> {
+    in_range(value, 0, 0xF, "nibble")
+}
+
+/// Negative bytes down to -128 are stored in two's complement
+fn byte(value: i64) -> Result<u16, String> {
+    Ok(in_range(value, -0x80, 0xFF, "byte")? & 0xFF)
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    in_range(value, 0, 0xFFF, "address")
+}
+
+/// Evaluates a sum of numbers and symbols, e.g. sprites + 5 - #1
+fn evaluate(expression: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let expression = expression.trim();
+    if expression.is_empty() {
+        return Err("missing value".to_string());
+    }
+
+    let mut total: i64 = 0;
+    let mut sign: i64 = 1;
+    let mut term = String::new();
+
+    for c in expression.chars().chain(std::iter::once('+')) {
+        match c {
+            '+' | '-' => {
+                let term_text = term.trim();
+                if term_text.is_empty() {
+                    if c == '-' {
+                        sign = -sign;
+                    }
+                    continue;
+                }
+
+                total = sign
+                    .checked_mul(parse_term(term_text, symbols)?)
+                    .and_then(|value| total.checked_add(value))
+                    .ok_or_else(|| format!("value out of range '{}'", expression))?;
+                sign = if c == '-' { -1 } else { 1 };
+                term.clear();
+            }
+            _ => term.push(c),
+        }
+    }
+    Ok(total)
+}
+
+/// Parses #FF, 0xFF or $FF hexadecimal, %0101 or 0b0101 binary, decimal or a symbol
+fn parse_term(term: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let number = if let Some(hex) = term
+        .strip_prefix('#')
+        .or_else(|| term.strip_prefix('$'))
+        .or_else(|| term.strip_prefix("0x"))
+    {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = term.strip_prefix('%').or_else(|| term.strip_prefix("0b")) {
+        i64::from_str_radix(binary, 2).ok()
+    } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+        term.parse().ok()
+    } else if is_symbol(term) {
+        return symbols
+            .get(term)
+            .copied()
+            .ok_or_else(|| format!("undefined symbol '{}'", term));
+    } else {
+        None
+    };
+
+    number.ok_or_else(|| format!("invalid value '{}'", term))
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(x), None) | (Some('v'), Some(x), None) => x.to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+/// LONG operand of the XO-CHIP F000 NNNN
+fn is_long(operand: &str) -> bool {
+    operand.len() > 5
+        && operand
+            .get(..5)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("long "))
+}
+
+fn is_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(';') {
+        Some(start) => &line[..start],
+        None => line,
+    }
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    text.split(',')
+        .map(|operand| operand.trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Platform;
+    use crate::disassembler::{Disassembler, Syntax};
+
+    #[test]
+    fn test_assemble() {
+        let source = "\
+; Bounces a ball
+BALL_X  EQU 10
+speed = 1
+
+start:  LD V0, BALL_X       ; x
+        LD V1, #05
+        LD I, ball
+loop:   DRW V0, V1, ball_end - ball
+        ADD V0, speed
+        ADD VB, -2
+        LD [I], V0 - V3
+        LD I, LONG end
+        JP loop
+ball:   db %11000000, $C0
+ball_end:
+        dw #1234
+end:";
+        let assembly = assemble(source).unwrap();
+
+        assert_eq!(
+            assembly.rom,
+            [
+                0x60, 0x0A, 0x61, 0x05, 0xA2, 0x14, 0xD0, 0x12, 0x70, 0x01, 0x7B, 0xFE, 0x50, 0x32,
+                0xF0, 0x00, 0x02, 0x18, 0x12, 0x06, 0xC0, 0xC0, 0x12, 0x34,
+            ]
+        );
+        assert_eq!(assembly.labels["loop"], 0x206);
+        assert_eq!(
+            assembly.symbol_file(),
+            "0x0200 start\n0x0206 loop\n0x0214 ball\n0x0216 ball_end\n0x0218 end\n"
+        );
+
+        let symbols = assemble("start:\nmain: CLS").unwrap().symbols();
+        assert_eq!(symbols.address("start"), Some(0x200));
+        assert_eq!(symbols.address("main"), Some(0x200));
+    }
+
+    #[test]
+    fn test_errors() {
+        let source = "\
+        LD V0, #100
+        JP nowhere
+twice:
+twice:
+        FOO V1
+        DRW V0, V1
+        PITCH 5
+        LD I, lé€x";
+        let errors = assemble(source).unwrap_err();
+        let messages: Vec<(usize, &str)> = errors
+            .iter()
+            .map(|error| (error.line, error.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            messages,
+            [
+                (4, "'twice' is already defined"),
+                (1, "byte 256 is out of range"),
+                (2, "undefined symbol 'nowhere'"),
+                (5, "unknown mnemonic 'FOO'"),
+                (6, "invalid operands for DRW"),
+                (7, "invalid operands for PITCH"),
+                (8, "invalid value 'lé€x'"),
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "<source>:4: 'twice' is already defined"
+        );
+        assert_eq!(
+            assemble("; nothing").unwrap_err()[0].message,
+            "no code or data to assemble"
+        );
+        assert_eq!(
+            assemble("org 0x10000").unwrap_err()[0].message,
+            "org 0x10000 is past the end of memory 0xffff"
+        );
+        assert_eq!(
+            assemble("db 9223372036854775807 + 1").unwrap_err()[0].message,
+            "value out of range '9223372036854775807 + 1'"
+        );
+    }
+
+    #[test]
+    fn test_disassembly_roundtrip() {
+        let rom = fs::read("roms/pong.rom").unwrap();
+        let disassembler = Disassembler::new(Syntax::Conventional, Platform::XoChip);
+        let source: Vec<String> = disassembler
+            .disassemble(&rom, PROGRAM_START)
+            .into_iter()
+            .map(|instruction| instruction.text)
+            .collect();
+
+        assert_eq!(assemble(&source.join("\n")).unwrap().rom, rom);
+    }
+}
@@ -0,0 +1,110 @@
+use chip8_emulator::assembler::{self, Assembly};
+use chip8_emulator::octo;
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "Usage: chip8-asm [OPTIONS] <SOURCE>
+
+Assembles a program written with the conventional mnemonics into a ROM, or compiles
+an Octo program when SOURCE has a .8o extension
+
+Options:
+    --octo                  Compile SOURCE as Octo whatever its extension
+    --output <FILE>         Where to write the ROM (default: SOURCE with a .ch8 extension)
+    --symbols <FILE>        Also write the address of each label to FILE
+    -h, --help              Print this message";
+
+struct Options {
+    source: String,
+    output: Option<String>,
+    symbols: Option<String>,
+    octo: bool,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(&options) {
+        eprintln!("chip8-asm: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    let assembly = if options.octo {
+        octo::compile_file(&options.source)?
+    } else {
+        assemble(&options.source)?
+    };
+
+    let output = match &options.output {
+        Some(path) => path.clone(),
+        None => Path::new(&options.source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    fs::write(&output, &assembly.rom)
+        .map_err(|error| format!("can't write ROM '{}': {}", output, error))?;
+
+    if let Some(path) = &options.symbols {
+        fs::write(path, assembly.symbol_file())
+            .map_err(|error| format!("can't write symbols '{}': {}", path, error))?;
+    }
+    Ok(())
+}
+
+fn assemble(source: &str) -> Result<Assembly, String> {
+    assembler::assemble_file(source).map_err(|errors| {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        format!("{} error(s), no ROM written", errors.len())
+    })
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut source = None;
+    let mut options = Options {
+        source: String::new(),
+        output: None,
+        symbols: None,
+        octo: false,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--output" => options.output = Some(value()?),
+            "--symbols" => options.symbols = Some(value()?),
+            "--octo" => options.octo = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    options.source = source.ok_or("missing source path")?;
+    options.octo |= Path::new(&options.source)
+        .extension()
+        .is_some_and(|extension| extension == "8o");
+    Ok(options)
+}
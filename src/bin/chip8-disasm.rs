@@ -0,0 +1,121 @@
+use chip8_emulator::chip8::{Platform, SymbolTable, PROGRAM_START};
+use chip8_emulator::disassembler::{self, Disassembler, Syntax};
+use chip8_emulator::number::parse_number;
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+const USAGE: &str = "Usage: chip8-disasm [OPTIONS] <ROM>
+
+Prints the instructions of a ROM, one per line
+
+Options:
+    --syntax <NAME>         conventional or octo (default: conventional)
+    --platform <NAME>       Only decode the opcodes of vip, chip48, schip or xochip (default: xochip)
+    --base <ADDR>           Address the ROM is loaded at (default: 0x200)
+    --trace                 Follow the control flow from the base address to tell code from data
+    --entry <ADDR>          Also trace from ADDR, e.g. a computed jump target, implies --trace
+    --symbols <FILE>        Name addresses with the labels of a symbol file
+    --output <FILE>         Write the listing to FILE instead of stdout
+    -h, --help              Print this message";
+
+struct Options {
+    rom: String,
+    disassembler: Disassembler,
+    base_address: usize,
+    trace: bool,
+    entry_points: Vec<usize>,
+    symbols: Option<String>,
+    output: Option<String>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(&options) {
+        eprintln!("chip8-disasm: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    let rom = fs::read(&options.rom)
+        .map_err(|error| format!("can't read ROM '{}': {}", options.rom, error))?;
+
+    let symbols = match &options.symbols {
+        Some(path) => SymbolTable::load(path)?,
+        None => SymbolTable::new(),
+    };
+    let labels = symbols.names();
+
+    let disassembler = &options.disassembler;
+    let mut listing = if options.trace {
+        let mut entry_points = vec![options.base_address];
+        entry_points.extend(&options.entry_points);
+        disassembler
+            .trace_with_labels(&rom, options.base_address, &entry_points, labels)
+            .listing()
+    } else {
+        let instructions = disassembler.disassemble_with_labels(&rom, options.base_address, labels);
+        disassembler::listing_with_labels(&instructions, disassembler.syntax, labels)
+    };
+    listing.push('\n');
+
+    match &options.output {
+        Some(path) => fs::write(path, listing)?,
+        None => io::stdout().write_all(listing.as_bytes())?,
+    }
+    Ok(())
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        disassembler: Disassembler::default(),
+        base_address: PROGRAM_START,
+        trace: false,
+        entry_points: Vec::new(),
+        symbols: None,
+        output: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--syntax" => options.disassembler.syntax = value()?.parse::<Syntax>()?,
+            "--platform" => options.disassembler.platform = value()?.parse::<Platform>()?,
+            "--base" => options.base_address = parse_number(&value()?)?,
+            "--trace" => options.trace = true,
+            "--entry" => {
+                options.entry_points.push(parse_number(&value()?)?);
+                options.trace = true;
+            }
+            "--symbols" => options.symbols = Some(value()?),
+            "--output" => options.output = Some(value()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or("missing ROM path")?;
+    Ok(options)
+}
@@ -0,0 +1,485 @@
+use chip8_emulator::chip8::{self, Platform, SymbolTable, TraceFormat, Tracer};
+use chip8_emulator::console::{self, DebugConsole};
+use chip8_emulator::number::parse_number;
+use chip8_emulator::palette::Palette;
+use chip8_emulator::recording::{Recording, RecordingFormat};
+use chip8_emulator::screenshot::Screenshot;
+use chip8_emulator::sound::SoundRecorder;
+use chip8_emulator::Chip8;
+
+use std::env;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+const USAGE: &str = "Usage: chip8-headless [OPTIONS] <ROM>
+
+Runs a ROM without a display and dumps the final machine state
+
+Options:
+    --platform <NAME>       legacy, vip, chip48, schip or xochip (default: legacy)
+    --ipf <N>               Instructions executed per 60 Hz frame
+    --load-address <ADDR>   Address where the ROM is loaded and starts (default: 0x200)
+    --frames <N>            Maximum number of frames to run (default: 600)
+    --stop-at <ADDR>        Stop once pc reaches ADDR
+    --break <ADDR>          Start the debugger once pc reaches ADDR, implies --debug
+    --debug                 Start paused, reading debugger commands from stdin ('q' quits)
+    --symbols <FILE>        Show the labels of a symbol file, ADDR arguments can be labels
+    --trace <FILE>          Log every executed instruction and its effects to FILE
+    --trace-format <NAME>   text or binary (default: text)
+    --trace-range <A:B>     Only log instructions between A and B, can be repeated
+    --wav <FILE>            Record the sound timer beep and XO-CHIP audio to a WAV file
+    --sample-rate <HZ>      Sample rate of the --wav recording (default: 44100)
+    --screenshot <N:FILE>   Save the display as a PNG after N frames, can be repeated
+    --record <FILE>         Record the display of every frame to FILE
+    --record-format <NAME>  gif, or raw RGB frames for ffmpeg (default: gif)
+    --scale <N>             Size of each CHIP-8 pixel in screenshots and recordings (default: 1)
+    --palette <COLORS>      Palette preset or comma separated RRGGBB colours (default: classic)
+    --key <FRAME:KEY[:N]>   Hold KEY (0-F) for N frames starting at FRAME (default N: 1)
+    --script <FILE>         Read key presses from FILE, one 'FRAME KEY [N]' per line
+    --dump-memory           Also dump the whole memory
+    --output <FILE>         Write the dump to FILE instead of stdout
+    -h, --help              Print this message";
+
+const DEFAULT_FRAMES: u64 = 600;
+
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// Key held down during frames [frame, frame + duration)
+struct KeyPress {
+    frame: u64,
+    key: u8,
+    duration: u64,
+}
+
+struct Options {
+    rom: String,
+    platform: Platform,
+    instructions_per_frame: Option<usize>,
+    load_address: Option<usize>,
+    frames: u64,
+    stop_at: Option<String>,
+    breakpoints: Vec<String>,
+    debug: bool,
+    symbols: Option<String>,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_ranges: Vec<(String, String)>,
+    wav: Option<String>,
+    sample_rate: u32,
+    screenshots: Vec<(u64, String)>,
+    record: Option<String>,
+    record_format: RecordingFormat,
+    scale: usize,
+    palette: Palette,
+    key_presses: Vec<KeyPress>,
+    dump_memory: bool,
+    output: Option<String>,
+}
+
+/// Why the run ended
+enum StopReason {
+    FrameLimit,
+    Halted,
+    InfiniteLoop,
+    StopAddress,
+    Quit,
+    Error(String),
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("chip8-headless: {}", error);
+            process::exit(2);
+        }
+    }
+}
+
+/// Runs the ROM and writes the dump. Returns false if emulation stopped on an error
+fn run(options: &Options) -> Result<bool, Box<dyn Error>> {
+    let mut chip8 = Chip8::with_platform(options.platform);
+    if let Some(instructions) = options.instructions_per_frame {
+        chip8.set_instructions_per_frame(instructions);
+    }
+    if let Some(address) = options.load_address {
+        chip8.set_load_address(address);
+    }
+    chip8
+        .load_program(&options.rom)
+        .map_err(|error| format!("can't load ROM '{}': {}", options.rom, error))?;
+
+    if let Some(path) = &options.symbols {
+        chip8.debugger_mut().set_symbols(SymbolTable::load(path)?);
+    }
+    let symbols = chip8.debugger().symbols().clone();
+
+    let stop_at = match &options.stop_at {
+        Some(address) => Some(symbols.resolve(address)?),
+        None => None,
+    };
+    if let Some(address) = stop_at {
+        chip8.debugger_mut().add_breakpoint(address);
+    }
+    for address in &options.breakpoints {
+        chip8
+            .debugger_mut()
+            .add_breakpoint(symbols.resolve(address)?);
+    }
+
+    if let Some(path) = &options.trace {
+        let mut tracer = Tracer::create(path, options.trace_format)
+            .map_err(|error| format!("can't create trace '{}': {}", path, error))?;
+        for (start, end) in &options.trace_ranges {
+            tracer.add_range(symbols.resolve(start)?, symbols.resolve(end)?);
+        }
+        chip8.set_tracer(tracer);
+    }
+    let mut recorder = options
+        .wav
+        .as_ref()
+        .map(|_| SoundRecorder::new(options.sample_rate));
+    let mut recording = match &options.record {
+        Some(path) => Some(
+            Recording::create(
+                path,
+                options.record_format,
+                &chip8,
+                &options.palette,
+                options.scale,
+            )
+            .map_err(|error| format!("can't create recording '{}': {}", path, error))?,
+        ),
+        None => None,
+    };
+    if options.debug && options.breakpoints.is_empty() {
+        chip8.pause();
+    }
+
+    let mut console = DebugConsole::new();
+    let mut commands = io::stdin().lock().lines();
+    let mut screenshots = options.screenshots.clone();
+    let mut frame = 0;
+    let reason = loop {
+        for (_, path) in screenshots.iter().filter(|(at, _)| *at == frame) {
+            Screenshot::capture(&chip8, &options.palette, options.scale)
+                .save_png(path)
+                .map_err(|error| format!("can't write '{}': {}", path, error))?;
+        }
+        screenshots.retain(|(at, _)| *at != frame);
+
+        if frame >= options.frames {
+            break StopReason::FrameLimit;
+        }
+        if chip8.is_halted() {
+            break StopReason::Halted;
+        }
+        if let Some(reason) = chip8.debugger_mut().take_stop_reason() {
+            if !options.debug || stop_at == Some(chip8.pc()) {
+                match reason {
+                    chip8::StopReason::Error(error) => break StopReason::Error(error.to_string()),
+                    _ => break StopReason::StopAddress,
+                }
+            }
+            eprintln!("{}", console::stop_message(&chip8, reason));
+        }
+        if chip8.is_paused() {
+            eprint!("(chip8) ");
+            match commands.next().transpose()? {
+                Some(line) if !matches!(line.trim(), "q" | "quit") => {
+                    let output = console.execute(&mut chip8, &line);
+                    if !output.is_empty() {
+                        eprintln!("{}", output);
+                    }
+                    continue;
+                }
+                _ => break StopReason::Quit,
+            }
+        }
+        if is_jumping_to_itself(&chip8) {
+            break StopReason::InfiniteLoop;
+        }
+
+        for key in 0..16 {
+            if is_key_held(&options.key_presses, key, frame) {
+                chip8.press_key(key)?;
+            } else {
+                chip8.release_key(key)?;
+            }
+        }
+
+        // Errors also stop the debugger, and are reported above
+        if chip8.run_frame().is_ok() {
+            frame += 1;
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record_frame(&chip8);
+            }
+            if let Some(recording) = recording.as_mut() {
+                recording.record_frame(&chip8);
+            }
+        }
+    };
+
+    if let Some(tracer) = chip8.take_tracer() {
+        tracer
+            .finish()
+            .map_err(|error| format!("can't write trace: {}", error))?;
+    }
+
+    if let Some(recording) = recording {
+        recording
+            .finish()
+            .map_err(|error| format!("can't write the recording: {}", error))?;
+    }
+    if let (Some(recorder), Some(path)) = (&recorder, &options.wav) {
+        recorder
+            .save_wav(path)
+            .map_err(|error| format!("can't write '{}': {}", path, error))?;
+    }
+
+    let dump = dump(&chip8, frame, &reason, options.dump_memory);
+    match &options.output {
+        Some(path) => fs::write(path, dump)?,
+        None => io::stdout().write_all(dump.as_bytes())?,
+    }
+
+    if let StopReason::Error(error) = reason {
+        eprintln!("Emulation stopped: {}", error);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// A 1NNN jumping to its own address, the usual way of ending a CHIP-8 program
+fn is_jumping_to_itself(chip8: &Chip8) -> bool {
+    let pc = chip8.pc();
+
+    match chip8.memory().get(pc..pc + 2) {
+        Some(&[high, low]) => {
+            let opcode = (high as usize) << 8 | low as usize;
+            opcode & 0xF000 == 0x1000 && opcode & 0x0FFF == pc
+        }
+        _ => false,
+    }
+}
+
+fn is_key_held(key_presses: &[KeyPress], key: u8, frame: u64) -> bool {
+    key_presses.iter().any(|press| {
+        press.key == key
+            && frame >= press.frame
+            && frame < press.frame.saturating_add(press.duration)
+    })
+}
+
+fn dump(chip8: &Chip8, frames: u64, reason: &StopReason, dump_memory: bool) -> String {
+    let mut out = String::new();
+
+    let reason = match reason {
+        StopReason::FrameLimit => "frame limit reached".to_string(),
+        StopReason::Halted => "program exited".to_string(),
+        StopReason::InfiniteLoop => "infinite loop".to_string(),
+        StopReason::StopAddress => "stop address reached".to_string(),
+        StopReason::Quit => "quit from the debugger".to_string(),
+        StopReason::Error(error) => format!("error: {}", error),
+    };
+    let _ = writeln!(out, "frames: {} ({})", frames, reason);
+
+    let _ = writeln!(
+        out,
+        "pc: {:#06x}  I: {:#06x}  SP: {}  DT: {}  ST: {}",
+        chip8.pc(),
+        chip8.index(),
+        chip8.stack_pointer(),
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    );
+    for (row, registers) in chip8.registers().chunks(8).enumerate() {
+        let line: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X}: {:02x}", row * 8 + i, value))
+            .collect();
+        let _ = writeln!(out, "{}", line.join("  "));
+    }
+    let stack: Vec<String> = chip8.stack()[..chip8.stack_pointer()]
+        .iter()
+        .map(|address| format!("{:#06x}", address))
+        .collect();
+    let _ = writeln!(out, "stack: [{}]", stack.join(", "));
+
+    let (width, _) = chip8.display_size();
+    let _ = writeln!(out, "display:");
+    for line in chip8.display().chunks(width) {
+        let line: String = line
+            .iter()
+            .map(|&pixel| match pixel {
+                0 => '.',
+                1 => '#',
+                2 => '+',
+                _ => '@',
+            })
+            .collect();
+        let _ = writeln!(out, "{}", line);
+    }
+
+    if dump_memory {
+        let _ = writeln!(out, "memory:");
+        for (row, bytes) in chip8.memory().chunks(16).enumerate() {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let _ = writeln!(out, "{:04x}: {}", row * 16, bytes.join(" "));
+        }
+    }
+
+    out
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        platform: Platform::default(),
+        instructions_per_frame: None,
+        load_address: None,
+        frames: DEFAULT_FRAMES,
+        stop_at: None,
+        breakpoints: Vec::new(),
+        debug: false,
+        symbols: None,
+        trace: None,
+        trace_format: TraceFormat::default(),
+        trace_ranges: Vec::new(),
+        wav: None,
+        sample_rate: DEFAULT_SAMPLE_RATE,
+        screenshots: Vec::new(),
+        record: None,
+        record_format: RecordingFormat::default(),
+        scale: 1,
+        palette: Palette::default(),
+        key_presses: Vec::new(),
+        dump_memory: false,
+        output: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--platform" => options.platform = value()?.parse()?,
+            "--ipf" => options.instructions_per_frame = Some(parse_number(&value()?)?),
+            "--load-address" => options.load_address = Some(parse_number(&value()?)?),
+            "--frames" => options.frames = parse_number(&value()?)? as u64,
+            "--stop-at" => options.stop_at = Some(value()?),
+            "--break" => {
+                options.breakpoints.push(value()?);
+                options.debug = true;
+            }
+            "--debug" => options.debug = true,
+            "--symbols" => options.symbols = Some(value()?),
+            "--trace" => options.trace = Some(value()?),
+            "--trace-format" => options.trace_format = value()?.parse()?,
+            "--trace-range" => {
+                let text = value()?;
+                let (start, end) = text
+                    .split_once(':')
+                    .ok_or_else(|| format!("invalid trace range '{}', expected A:B", text))?;
+                options
+                    .trace_ranges
+                    .push((start.to_string(), end.to_string()));
+            }
+            "--wav" => options.wav = Some(value()?),
+            "--sample-rate" => {
+                let text = value()?;
+                options.sample_rate = match text.parse() {
+                    Ok(rate) if rate > 0 => rate,
+                    _ => return Err(format!("invalid sample rate '{}'", text)),
+                };
+            }
+            "--screenshot" => {
+                let text = value()?;
+                let (frame, path) = text
+                    .split_once(':')
+                    .ok_or_else(|| format!("invalid screenshot '{}', expected FRAME:FILE", text))?;
+                options
+                    .screenshots
+                    .push((parse_number(frame)? as u64, path.to_string()));
+            }
+            "--record" => options.record = Some(value()?),
+            "--record-format" => options.record_format = value()?.parse()?,
+            "--scale" => {
+                let text = value()?;
+                options.scale = match text.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("invalid scale '{}'", text)),
+                };
+            }
+            "--palette" => options.palette = value()?.parse()?,
+            "--key" => options.key_presses.push(parse_key_press(&value()?, ':')?),
+            "--script" => {
+                let path = value()?;
+                let script = fs::read_to_string(&path)
+                    .map_err(|error| format!("can't read {}: {}", path, error))?;
+                for line in script.lines().map(str::trim) {
+                    if !line.is_empty() && !line.starts_with('#') {
+                        options.key_presses.push(parse_key_press(line, ' ')?);
+                    }
+                }
+            }
+            "--dump-memory" => options.dump_memory = true,
+            "--output" => options.output = Some(value()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or("missing ROM path")?;
+    Ok(options)
+}
+
+/// Parses FRAME<separator>KEY[<separator>DURATION], KEY being a hex digit
+fn parse_key_press(text: &str, separator: char) -> Result<KeyPress, String> {
+    let fields: Vec<&str> = text
+        .split(separator)
+        .filter(|field| !field.is_empty())
+        .collect();
+    let invalid = || format!("invalid key press '{}'", text);
+
+    if fields.len() < 2 || fields.len() > 3 {
+        return Err(invalid());
+    }
+
+    let frame = parse_number(fields[0])? as u64;
+    let key = u8::from_str_radix(fields[1], 16).map_err(|_| invalid())?;
+    if key >= 16 {
+        return Err(invalid());
+    }
+    let duration = match fields.get(2) {
+        Some(duration) => parse_number(duration)? as u64,
+        None => 1,
+    };
+
+    Ok(KeyPress {
+        frame,
+        key,
+        duration,
+    })
+}
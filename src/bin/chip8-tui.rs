@@ -0,0 +1,609 @@
+use chip8_emulator::chip8::{Platform, SymbolTable, FRAME_RATE};
+use chip8_emulator::console::DebugConsole;
+use chip8_emulator::disassembler::{Disassembler, Syntax};
+use chip8_emulator::gdb::{GdbStub, Session};
+use chip8_emulator::number::parse_number;
+use chip8_emulator::palette::Palette;
+use chip8_emulator::Chip8;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::io::{self, Stdout, Write};
+use std::process;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: chip8-tui [OPTIONS] <ROM>
+
+Runs a ROM in the terminal, next to debugger panes
+
+Options:
+    --platform <NAME>       legacy, vip, chip48, schip or xochip (default: legacy)
+    --ipf <N>               Instructions executed per 60 Hz frame
+    --load-address <ADDR>   Address where the ROM is loaded and starts (default: 0x200)
+    --palette <COLORS>      Palette preset or comma separated RRGGBB colours (default: classic)
+    --symbols <FILE>        Show the labels of a symbol file, ADDR arguments can be labels
+    --break <ADDR>          Pause once pc reaches ADDR, can be repeated
+    --paused                Start paused
+    --gdb <ADDR>            Wait for a GDB remote protocol debugger on ADDR, e.g. localhost:1234
+    -h, --help              Print this message
+
+Keys:
+    1-4 Q-R A-F Z-V         CHIP-8 keypad
+    P                       Pause / resume
+    B                       Toggle a breakpoint at pc
+    F11                     Step into (execute one instruction)
+    F10                     Step over subroutine calls
+    Shift+F11               Step out of the current subroutine
+    PageUp / PageDown       Scroll the memory view, Home makes it follow I again
+    :                       Type a debugger command, ':help' lists them
+    Esc, Ctrl+C             Quit";
+
+const KEYPAD: [(char, u8); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xC),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xD),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xE),
+    ('z', 0xA),
+    ('x', 0x0),
+    ('c', 0xB),
+    ('v', 0xF),
+];
+
+/// Terminals only report key presses, so each press holds the key for a few frames
+const KEY_HOLD_FRAMES: u32 = 8;
+
+const REGISTERS_WIDTH: usize = 24;
+const DISASSEMBLY_WIDTH: usize = 40;
+const STACK_WIDTH: usize = 22;
+const MEMORY_WIDTH: usize = 53;
+const MIDDLE_HEIGHT: usize = 10;
+const CONSOLE_HEIGHT: usize = 6;
+const MEMORY_ROWS: usize = MIDDLE_HEIGHT;
+
+/// Instructions shown above pc in the disassembly
+const DISASSEMBLY_CONTEXT: usize = 3;
+
+struct Options {
+    rom: String,
+    platform: Platform,
+    instructions_per_frame: Option<usize>,
+    load_address: Option<usize>,
+    palette: Palette,
+    symbols: Option<String>,
+    breakpoints: Vec<String>,
+    paused: bool,
+    gdb: Option<String>,
+}
+
+/// Everything the frontend keeps besides the machine
+struct Ui {
+    console: DebugConsole,
+    /// Console output, the oldest line first
+    messages: VecDeque<String>,
+    /// Debugger command being typed after ':'
+    command: Option<String>,
+    /// First address of the memory view, None to follow I
+    memory_start: Option<usize>,
+    /// Frames left to hold each CHIP-8 key down
+    held_keys: [u32; 16],
+    /// Lines drawn last frame, only the changed ones are drawn again
+    screen: Vec<String>,
+    quit: bool,
+}
+
+/// Restores the terminal when dropped, even when the frontend fails
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(&options) {
+        eprintln!("chip8-tui: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut chip8 = Chip8::with_platform(options.platform);
+    if let Some(instructions) = options.instructions_per_frame {
+        chip8.set_instructions_per_frame(instructions);
+    }
+    if let Some(address) = options.load_address {
+        chip8.set_load_address(address);
+    }
+    chip8
+        .load_program(&options.rom)
+        .map_err(|error| format!("can't load ROM '{}': {}", options.rom, error))?;
+
+    if let Some(path) = &options.symbols {
+        chip8.debugger_mut().set_symbols(SymbolTable::load(path)?);
+    }
+    for address in &options.breakpoints {
+        let address = chip8.debugger().symbols().resolve(address)?;
+        chip8.debugger_mut().add_breakpoint(address);
+    }
+    if options.paused {
+        chip8.pause();
+    }
+
+    let mut gdb = match &options.gdb {
+        Some(address) => {
+            println!("Waiting for a debugger on {}", address);
+            Some(GdbStub::listen(address.as_str())?)
+        }
+        None => None,
+    };
+
+    let mut ui = Ui {
+        console: DebugConsole::new(),
+        messages: VecDeque::new(),
+        command: None,
+        memory_start: None,
+        held_keys: [0; 16],
+        screen: Vec::new(),
+        quit: false,
+    };
+    ui.message("Press ':' then type 'help' for the debugger commands, Esc quits");
+
+    terminal::enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, Hide)?;
+
+    let frame_duration = Duration::from_secs(1) / FRAME_RATE;
+    let mut next_frame = Instant::now();
+    while !ui.quit {
+        let now = Instant::now();
+        if next_frame > now && event::poll(next_frame - now)? {
+            if let Event::Key(key) = event::read()? {
+                handle_key(&mut chip8, &mut ui, key);
+            }
+            continue;
+        }
+        next_frame = (next_frame + frame_duration).max(Instant::now());
+
+        for (key, frames) in ui.held_keys.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                chip8.press_key(key as u8)?;
+            } else {
+                chip8.release_key(key as u8)?;
+            }
+        }
+        // Errors pause the debugger, they are reported with the other stops below
+        let _ = chip8.run_frame();
+        if let Some(reason) = chip8.debugger_mut().take_stop_reason() {
+            ui.message(&format!("stopped: {}", reason));
+        }
+        if let Some(stub) = &mut gdb {
+            match stub.poll(&mut chip8)? {
+                Session::Attached => (),
+                Session::Detached => {
+                    gdb = None;
+                    ui.message("Debugger detached");
+                }
+                Session::Killed => break,
+            }
+        }
+
+        draw(&mut stdout, &chip8, &mut ui, &options.palette)?;
+    }
+    Ok(())
+}
+
+fn handle_key(chip8: &mut Chip8, ui: &mut Ui, key: KeyEvent) {
+    if key.kind == KeyEventKind::Release {
+        return;
+    }
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        ui.quit = true;
+        return;
+    }
+
+    if let Some(command) = ui.command.as_mut() {
+        match key.code {
+            KeyCode::Char(c) => command.push(c),
+            KeyCode::Backspace => {
+                command.pop();
+            }
+            KeyCode::Esc => ui.command = None,
+            KeyCode::Enter => {
+                let command = ui.command.take().unwrap_or_default();
+                ui.message(&format!(":{}", command));
+                let output = ui.console.execute(chip8, &command);
+                ui.message(&output);
+            }
+            _ => (),
+        }
+        return;
+    }
+
+    let stepped = match key.code {
+        KeyCode::Esc => {
+            ui.quit = true;
+            None
+        }
+        KeyCode::Char(':') => {
+            ui.command = Some(String::new());
+            None
+        }
+        KeyCode::Char('p') | KeyCode::Char('P') => {
+            if chip8.is_paused() {
+                chip8.resume();
+            } else {
+                chip8.pause();
+            }
+            None
+        }
+        KeyCode::Char('b') | KeyCode::Char('B') => {
+            let pc = chip8.pc();
+            let debugger = chip8.debugger_mut();
+            if !debugger.remove_breakpoint(pc) {
+                debugger.add_breakpoint(pc);
+            }
+            None
+        }
+        KeyCode::F(10) => Some(chip8.step_over()),
+        KeyCode::F(11) if !key.modifiers.contains(KeyModifiers::SHIFT) => Some(chip8.step_into()),
+        KeyCode::F(11) => {
+            if !chip8.step_out() {
+                ui.message("not inside a subroutine");
+            }
+            None
+        }
+        KeyCode::PageUp => {
+            let start = memory_start(chip8, ui);
+            ui.memory_start = Some(start.saturating_sub(16 * MEMORY_ROWS));
+            None
+        }
+        KeyCode::PageDown => {
+            let start = memory_start(chip8, ui);
+            ui.memory_start = Some(start + 16 * MEMORY_ROWS);
+            None
+        }
+        KeyCode::Home => {
+            ui.memory_start = None;
+            None
+        }
+        KeyCode::Char(c) => {
+            let c = c.to_ascii_lowercase();
+            if let Some(&(_, key)) = KEYPAD.iter().find(|&&(known, _)| known == c) {
+                ui.held_keys[key as usize] = KEY_HOLD_FRAMES;
+            }
+            None
+        }
+        _ => None,
+    };
+
+    // Steps stop the debugger, the stop is described by the disassembly pane instead
+    if let Some(result) = stepped {
+        chip8.debugger_mut().take_stop_reason();
+        if let Err(error) = result {
+            ui.message(&format!("stopped: {}", error));
+        }
+    }
+}
+
+impl Ui {
+    fn message(&mut self, text: &str) {
+        for line in text.lines() {
+            self.messages.push_back(line.to_string());
+        }
+        while self.messages.len() > CONSOLE_HEIGHT {
+            self.messages.pop_front();
+        }
+    }
+}
+
+fn memory_start(chip8: &Chip8, ui: &Ui) -> usize {
+    let start = ui.memory_start.unwrap_or(chip8.index() & !0xF);
+    start.min(chip8.memory().len().saturating_sub(16 * MEMORY_ROWS))
+}
+
+/// Draws every pane, only writing the lines that changed since the last frame
+fn draw(stdout: &mut Stdout, chip8: &Chip8, ui: &mut Ui, palette: &Palette) -> io::Result<()> {
+    let (width, height) = chip8.display_size();
+
+    let top = side_by_side(
+        boxed("Display", width, display_lines(chip8, palette)),
+        boxed(
+            "Registers",
+            REGISTERS_WIDTH,
+            pad_lines(registers_lines(chip8), height / 2),
+        ),
+    );
+    let middle = side_by_side(
+        side_by_side(
+            boxed("Disassembly", DISASSEMBLY_WIDTH, disassembly_lines(chip8)),
+            boxed("Stack", STACK_WIDTH, stack_lines(chip8)),
+        ),
+        boxed("Memory", MEMORY_WIDTH, memory_lines(chip8, ui)),
+    );
+    let console_width = DISASSEMBLY_WIDTH + STACK_WIDTH + MEMORY_WIDTH + 4;
+    let console_lines = pad_lines(ui.messages.iter().cloned().collect(), CONSOLE_HEIGHT);
+    let bottom = boxed("Console", console_width, console_lines);
+
+    let mut lines: Vec<String> = top.into_iter().chain(middle).chain(bottom).collect();
+    lines.push(match &ui.command {
+        Some(command) => format!(":{}_", command),
+        None => {
+            let state = if chip8.is_paused() { "PAUSED" } else { "RUNNING" };
+            format!(
+                "{}  P run/pause  B breakpoint  F10 next  F11 step  Shift+F11 finish  : command  Esc quit",
+                state
+            )
+        }
+    });
+
+    let (columns, rows) = terminal::size()?;
+    let needed_columns = (width + REGISTERS_WIDTH + 4).max(console_width + 2);
+    if (columns as usize) < needed_columns || (rows as usize) < lines.len() {
+        lines = vec![format!(
+            "The terminal is {}x{}, {}x{} is needed",
+            columns,
+            rows,
+            needed_columns,
+            lines.len()
+        )];
+    }
+
+    if ui.screen.len() != lines.len() {
+        queue!(stdout, Clear(ClearType::All))?;
+        ui.screen.clear();
+    }
+    for (row, line) in lines.iter().enumerate() {
+        if ui.screen.get(row) != Some(line) {
+            queue!(
+                stdout,
+                MoveTo(0, row as u16),
+                Print(line),
+                Clear(ClearType::UntilNewLine)
+            )?;
+        }
+    }
+    ui.screen = lines;
+    stdout.flush()
+}
+
+/// Two display rows per line, drawn with upper half blocks in the palette colours
+fn display_lines(chip8: &Chip8, palette: &Palette) -> Vec<String> {
+    let (width, height) = chip8.display_size();
+    let display = chip8.display();
+
+    (0..height / 2)
+        .map(|row| {
+            let mut line = String::new();
+            for column in 0..width {
+                let (r, g, b) = palette.color(display[2 * row * width + column]);
+                let (br, bg, bb) = palette.color(display[(2 * row + 1) * width + column]);
+                line.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    r, g, b, br, bg, bb
+                ));
+            }
+            line.push_str("\x1b[0m");
+            line
+        })
+        .collect()
+}
+
+fn registers_lines(chip8: &Chip8) -> Vec<String> {
+    let registers = chip8.registers();
+    let mut lines = vec![
+        format!("PC {:#06x}   I {:#06x}", chip8.pc(), chip8.index()),
+        format!(
+            "SP {}  DT {:02x}  ST {:02x}",
+            chip8.stack_pointer(),
+            chip8.delay_timer(),
+            chip8.sound_timer()
+        ),
+        String::new(),
+    ];
+    for x in 0..8 {
+        lines.push(format!(
+            "V{:X} {:02x}       V{:X} {:02x}",
+            x,
+            registers[x],
+            x + 8,
+            registers[x + 8]
+        ));
+    }
+    lines
+}
+
+/// Instructions from a few before pc, the current one marked by '>' and breakpoints by '*'
+fn disassembly_lines(chip8: &Chip8) -> Vec<String> {
+    let disassembler = Disassembler::new(Syntax::Conventional, chip8.platform());
+    let symbols = chip8.debugger().symbols();
+    let memory = chip8.memory();
+    let pc = chip8.pc();
+
+    let mut lines = Vec::new();
+    let mut address = pc.saturating_sub(2 * DISASSEMBLY_CONTEXT);
+    while lines.len() < MIDDLE_HEIGHT && address < memory.len() {
+        if let Some(label) = symbols.name(address) {
+            lines.push(format!("{}:", label));
+        }
+
+        let instruction =
+            disassembler.decode_with_labels(&memory[address..], address, symbols.names());
+        let bytes: String = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        lines.push(format!(
+            "{}{} {:04x} {:<8} {}",
+            if address == pc { '>' } else { ' ' },
+            if chip8.debugger().has_breakpoint(address) {
+                '*'
+            } else {
+                ' '
+            },
+            address,
+            bytes,
+            instruction.text
+        ));
+        address += instruction.bytes.len();
+    }
+    pad_lines(lines, MIDDLE_HEIGHT)
+}
+
+/// Call sites, the innermost first
+fn stack_lines(chip8: &Chip8) -> Vec<String> {
+    let symbols = chip8.debugger().symbols();
+    let stack = &chip8.stack()[..chip8.stack_pointer()];
+    let lines = stack
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(depth, &address)| format!("#{} {}", depth + 1, symbols.describe(address as usize)))
+        .collect();
+    pad_lines(lines, MIDDLE_HEIGHT)
+}
+
+fn memory_lines(chip8: &Chip8, ui: &Ui) -> Vec<String> {
+    let start = memory_start(chip8, ui);
+    let memory = chip8.memory();
+
+    let lines = memory[start.min(memory.len())..]
+        .chunks(16)
+        .take(MEMORY_ROWS)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{:04x}: {}", start + row * 16, bytes.join(" "))
+        })
+        .collect();
+    pad_lines(lines, MIDDLE_HEIGHT)
+}
+
+/// Pads or cuts lines to exactly count lines
+fn pad_lines(mut lines: Vec<String>, count: usize) -> Vec<String> {
+    lines.resize(count, String::new());
+    lines
+}
+
+/// Surrounds lines with a border titled title. Plain lines are padded or cut to width,
+/// lines holding escape sequences must already be width characters wide
+fn boxed(title: &str, width: usize, lines: Vec<String>) -> Vec<String> {
+    let mut boxed = vec![format!(
+        "┌─ {} {}┐",
+        title,
+        "─".repeat(width - title.len() - 3)
+    )];
+    for line in lines {
+        let line = if line.contains('\x1b') {
+            line
+        } else {
+            let line: String = line.chars().take(width).collect();
+            format!("{:<width$}", line, width = width)
+        };
+        boxed.push(format!("│{}│", line));
+    }
+    boxed.push(format!("└{}┘", "─".repeat(width)));
+    boxed
+}
+
+/// Puts the lines of right after the ones of left, the shorter side being padded
+fn side_by_side(left: Vec<String>, right: Vec<String>) -> Vec<String> {
+    let rows = left.len().max(right.len());
+    let left_width = left.first().map_or(0, |line| visible_width(line));
+
+    (0..rows)
+        .map(|row| {
+            let left = left
+                .get(row)
+                .cloned()
+                .unwrap_or_else(|| " ".repeat(left_width));
+            let right = right.get(row).map(String::as_str).unwrap_or("");
+            format!("{}{}", left, right)
+        })
+        .collect()
+}
+
+/// Characters a line takes on screen, escape sequences excluded
+fn visible_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for c in line.chars() {
+        match c {
+            '\x1b' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if !in_escape => width += 1,
+            _ => (),
+        }
+    }
+    width
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        platform: Platform::default(),
+        instructions_per_frame: None,
+        load_address: None,
+        palette: Palette::default(),
+        symbols: None,
+        breakpoints: Vec::new(),
+        paused: false,
+        gdb: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--platform" => options.platform = value()?.parse()?,
+            "--ipf" => options.instructions_per_frame = Some(parse_number(&value()?)?),
+            "--load-address" => options.load_address = Some(parse_number(&value()?)?),
+            "--palette" => options.palette = value()?.parse()?,
+            "--symbols" => options.symbols = Some(value()?),
+            "--break" => options.breakpoints.push(value()?),
+            "--paused" => options.paused = true,
+            "--gdb" => options.gdb = Some(value()?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or("missing ROM path")?;
+    Ok(options)
+}
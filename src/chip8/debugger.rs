@@ -0,0 +1,700 @@
+use super::{Chip8, EmulationError, SymbolTable};
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Why the debugger paused the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Requested with Chip8::pause()
+    Pause,
+    /// pc reached a breakpoint
+    Breakpoint(usize),
+    /// A step into, over or out completed
+    Step,
+    /// pc reached the address given to Chip8::run_to()
+    RunToReached(usize),
+    /// A watchpoint was hit, by the instruction that just executed
+    Watchpoint(Watchpoint),
+    /// The instruction at pc failed
+    Error(EmulationError),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Pause => write!(f, "paused"),
+            StopReason::Breakpoint(address) => write!(f, "breakpoint at {:#05x}", address),
+            StopReason::Step => write!(f, "step"),
+            StopReason::RunToReached(address) => write!(f, "reached {:#05x}", address),
+            StopReason::Watchpoint(watchpoint) => write!(f, "watchpoint {}", watchpoint),
+            StopReason::Error(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Kind of memory access, made by an instruction or watched by a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    /// Whether a watchpoint on self catches an instruction making access
+    fn catches(self, access: Access) -> bool {
+        self == Access::ReadWrite || access == Access::ReadWrite || self == access
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "access"),
+        }
+    }
+}
+
+/// Comparison of a register with a value, for conditional watchpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Every comparison along with its operator
+    pub const ALL: [(Comparison, &'static str); 6] = [
+        (Comparison::Equal, "=="),
+        (Comparison::NotEqual, "!="),
+        (Comparison::Less, "<"),
+        (Comparison::LessOrEqual, "<="),
+        (Comparison::Greater, ">"),
+        (Comparison::GreaterOrEqual, ">="),
+    ];
+
+    pub fn operator(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(comparison, _)| *comparison == self)
+            .map_or("", |(_, operator)| operator)
+    }
+
+    pub fn holds(self, left: u8, right: u8) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+/// Condition stopping the machine, checked by Chip8::run_frame() around each instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// An instruction reading or writing memory between start and end, included
+    ///
+    /// Instruction fetches are not considered reads
+    Memory {
+        access: Access,
+        start: usize,
+        end: usize,
+    },
+    /// Register VX getting a new value
+    RegisterChange(u8),
+    /// Register VX starting to meet a condition
+    Register {
+        register: u8,
+        comparison: Comparison,
+        value: u8,
+    },
+    /// I starting to point between start and end, included
+    Index { start: usize, end: usize },
+}
+
+impl Watchpoint {
+    /// Whether the watchpoint catches an instruction making access to length bytes at
+    /// address
+    fn catches_memory(&self, access: Access, address: usize, length: usize) -> bool {
+        match *self {
+            Watchpoint::Memory {
+                access: watched,
+                start,
+                end,
+            } => {
+                watched.catches(access) && length > 0 && address <= end && start < address + length
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the watchpoint catches an instruction changing the registers and index from
+    /// before to after
+    fn catches_change(&self, before: &WatchedValues, after: &WatchedValues) -> bool {
+        match *self {
+            Watchpoint::Memory { .. } => false,
+            Watchpoint::RegisterChange(x) => {
+                before.registers[x as usize] != after.registers[x as usize]
+            }
+            Watchpoint::Register {
+                register,
+                comparison,
+                value,
+            } => {
+                let holds = |values: &WatchedValues| {
+                    comparison.holds(values.registers[register as usize], value)
+                };
+                !holds(before) && holds(after)
+            }
+            Watchpoint::Index { start, end } => {
+                let points_in = |values: &WatchedValues| (start..=end).contains(&values.index);
+                !points_in(before) && points_in(after)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Watchpoint::Memory { access, start, end } if start == end => {
+                write!(f, "{} {:#06x}", access, start)
+            }
+            Watchpoint::Memory { access, start, end } => {
+                write!(f, "{} {:#06x}-{:#06x}", access, start, end)
+            }
+            Watchpoint::RegisterChange(x) => write!(f, "V{:X} changes", x),
+            Watchpoint::Register {
+                register,
+                comparison,
+                value,
+            } => write!(
+                f,
+                "V{:X} {} {:#04x}",
+                register,
+                comparison.operator(),
+                value
+            ),
+            Watchpoint::Index { start, end } if start == end => write!(f, "I == {:#06x}", start),
+            Watchpoint::Index { start, end } => {
+                write!(f, "I in {:#06x}-{:#06x}", start, end)
+            }
+        }
+    }
+}
+
+/// Registers and index before an instruction, compared with the ones after it
+#[derive(Default)]
+struct WatchedValues {
+    registers: [u8; 16],
+    index: usize,
+}
+
+/// Pending step, checked around each instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepMode {
+    None,
+    /// Stops once the subroutine called from return_address - 2 returns
+    Over {
+        stack_pointer: usize,
+        return_address: usize,
+    },
+    /// Stops once the current subroutine returns, i.e. the stack gets below stack_pointer
+    Out {
+        stack_pointer: usize,
+    },
+    /// Stops before executing the instruction at address
+    RunTo(usize),
+}
+
+/// Breakpoints and pause state of a Chip8, enforced by Chip8::run_frame()
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    before_cycle: WatchedValues,
+    paused: bool,
+    step: StepMode,
+    stop_reason: Option<StopReason>,
+    /// Whether stop_reason hasn't been returned by take_stop_reason() yet
+    unreported_stop: bool,
+    /// Breakpoint ignored for the first instruction after resuming, so resuming from a
+    /// breakpoint doesn't stop right away
+    resume_pc: Option<usize>,
+    symbols: SymbolTable,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            before_cycle: WatchedValues::default(),
+            paused: false,
+            step: StepMode::None,
+            stop_reason: None,
+            unreported_stop: false,
+            resume_pc: None,
+            symbols: SymbolTable::new(),
+        }
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns false if there was already a breakpoint at address
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Returns false if there was no breakpoint at address
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn has_breakpoint(&self, address: usize) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    /// Breakpoint addresses, in ascending order
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Returns the position of the watchpoint, which identifies it in remove_watchpoint()
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    /// Removes the watchpoint at position, shifting the following ones
+    pub fn remove_watchpoint(&mut self, position: usize) -> Option<Watchpoint> {
+        if position < self.watchpoints.len() {
+            Some(self.watchpoints.remove(position))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Labels shown in place of addresses by debugging tools
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Why the machine last stopped, if it is paused
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        if self.paused {
+            self.stop_reason
+        } else {
+            None
+        }
+    }
+
+    /// Returns the reason of each stop only once, so frontends can report them
+    pub fn take_stop_reason(&mut self) -> Option<StopReason> {
+        if std::mem::take(&mut self.unreported_stop) {
+            self.stop_reason
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn stop(&mut self, reason: StopReason) {
+        self.paused = true;
+        self.step = StepMode::None;
+        self.stop_reason = Some(reason);
+        self.unreported_stop = true;
+    }
+
+    fn resume(&mut self, pc: usize, step: StepMode) {
+        self.paused = false;
+        self.step = step;
+        self.stop_reason = None;
+        self.unreported_stop = false;
+        self.resume_pc = Some(pc);
+    }
+
+    /// Keeps the breakpoints, watchpoints and pause state across Chip8::reset()
+    pub(super) fn after_reset(&mut self) -> Self {
+        Debugger {
+            breakpoints: std::mem::take(&mut self.breakpoints),
+            watchpoints: std::mem::take(&mut self.watchpoints),
+            paused: self.paused,
+            symbols: std::mem::take(&mut self.symbols),
+            ..Self::default()
+        }
+    }
+}
+
+impl Chip8 {
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Stops run_frame() from executing anything until resumed
+    pub fn pause(&mut self) {
+        self.debugger.stop(StopReason::Pause);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.debugger.is_paused()
+    }
+
+    /// Lets run_frame() execute instructions again, until the next breakpoint
+    pub fn resume(&mut self) {
+        self.debugger.resume(self.pc, StepMode::None);
+    }
+
+    /// Executes the instruction at pc right away, leaving the machine paused
+    ///
+    /// The stop reason is the watchpoint the instruction hit, if any
+    pub fn step_into(&mut self) -> Result<(), EmulationError> {
+        self.debugger.before_cycle = self.watched_values();
+        let result = self.emulate_cycle();
+
+        match result {
+            Ok(()) => {
+                let reason = self
+                    .hit_watchpoint()
+                    .map_or(StopReason::Step, StopReason::Watchpoint);
+                self.debugger.stop(reason);
+            }
+            Err(error) => self.debugger.stop(StopReason::Error(error)),
+        }
+        result
+    }
+
+    /// Same as step_into(), except that a subroutine call is run as a whole by
+    /// run_frame(), pausing once it returns
+    pub fn step_over(&mut self) -> Result<(), EmulationError> {
+        let is_call = self.memory.mem_array.get(self.pc).map(|byte| byte & 0xF0) == Some(0x20);
+
+        if is_call {
+            let step = StepMode::Over {
+                stack_pointer: self.memory.stack_pointer,
+                return_address: self.pc + 2,
+            };
+            self.debugger.resume(self.pc, step);
+            Ok(())
+        } else {
+            self.step_into()
+        }
+    }
+
+    /// Lets run_frame() execute until the current subroutine returns
+    ///
+    /// Returns false, leaving the machine paused, when not inside a subroutine
+    pub fn step_out(&mut self) -> bool {
+        if self.memory.stack_pointer == 0 {
+            return false;
+        }
+
+        let step = StepMode::Out {
+            stack_pointer: self.memory.stack_pointer,
+        };
+        self.debugger.resume(self.pc, step);
+        true
+    }
+
+    /// Lets run_frame() execute until pc reaches address, or a breakpoint is hit
+    pub fn run_to(&mut self, address: usize) {
+        self.debugger.resume(self.pc, StepMode::RunTo(address));
+    }
+
+    /// Checked by run_frame() before executing the instruction at pc. Returns true if the
+    /// machine stopped
+    pub(super) fn debug_before_cycle(&mut self) -> bool {
+        self.debugger.before_cycle = self.watched_values();
+        let resumed = self.debugger.resume_pc.take() == Some(self.pc);
+
+        let reason = if self.debugger.step == StepMode::RunTo(self.pc) {
+            Some(StopReason::RunToReached(self.pc))
+        } else if self.debugger.breakpoints.contains(&self.pc) {
+            Some(StopReason::Breakpoint(self.pc))
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason.filter(|_| !resumed) {
+            self.debugger.stop(reason);
+        }
+        self.debugger.paused
+    }
+
+    /// Checked by run_frame() after executing an instruction. Returns true if the machine
+    /// stopped
+    pub(super) fn debug_after_cycle(&mut self) -> bool {
+        let step_done = match self.debugger.step {
+            StepMode::Over {
+                stack_pointer,
+                return_address,
+            } => self.pc == return_address && self.memory.stack_pointer == stack_pointer,
+            StepMode::Out { stack_pointer } => self.memory.stack_pointer < stack_pointer,
+            _ => false,
+        };
+
+        if let Some(watchpoint) = self.hit_watchpoint() {
+            self.debugger.stop(StopReason::Watchpoint(watchpoint));
+        } else if step_done {
+            self.debugger.stop(StopReason::Step);
+        }
+        self.debugger.paused
+    }
+
+    /// First watchpoint caught by the instruction that just executed
+    fn hit_watchpoint(&self) -> Option<Watchpoint> {
+        let after = self.watched_values();
+        let debugger = &self.debugger;
+
+        debugger
+            .watchpoints
+            .iter()
+            .find(|watchpoint| {
+                watchpoint.catches_change(&debugger.before_cycle, &after)
+                    || self.last_access.is_some_and(|(access, address, length)| {
+                        watchpoint.catches_memory(access, address, length)
+                    })
+            })
+            .copied()
+    }
+
+    fn watched_values(&self) -> WatchedValues {
+        WatchedValues {
+            registers: self.cpu.register,
+            index: self.memory.index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 0x200: CALL 0x208 / 0x202: ADD V1, 1 / 0x204: JP 0x200
+    /// 0x208: ADD V0, 1 / 0x20A: ADD V0, 1 / 0x20C: RET
+    fn debugged_machine() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        let program = [
+            0x22, 0x08, 0x71, 0x01, 0x12, 0x00, 0x00, 0x00, 0x70, 0x01, 0x70, 0x01, 0x00, 0xEE,
+        ];
+        chip8.load_program_bytes(&program).unwrap();
+        chip8.set_instructions_per_frame(100);
+
+        chip8
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut chip8 = debugged_machine();
+        chip8.debugger_mut().add_breakpoint(0x20A);
+
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_paused());
+        assert_eq!(chip8.pc(), 0x20A);
+        assert_eq!(
+            chip8.debugger().stop_reason(),
+            Some(StopReason::Breakpoint(0x20A))
+        );
+        assert_eq!(chip8.register(0x0), 1);
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), 0x20A);
+
+        chip8.resume();
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), 0x20A);
+        assert_eq!(chip8.register(0x0), 3);
+        assert_eq!(chip8.register(0x1), 1);
+
+        assert!(chip8.debugger_mut().remove_breakpoint(0x20A));
+        chip8.resume();
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_paused());
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut chip8 = debugged_machine();
+        chip8.pause();
+
+        chip8.step_into().unwrap();
+        assert_eq!(chip8.pc(), 0x208);
+        assert!(chip8.is_paused());
+
+        assert!(chip8.step_out());
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), 0x202);
+        assert_eq!(chip8.register(0x0), 2);
+        assert_eq!(chip8.debugger().stop_reason(), Some(StopReason::Step));
+
+        chip8.step_over().unwrap();
+        chip8.step_over().unwrap();
+        assert_eq!(chip8.pc(), 0x200);
+        assert!(chip8.is_paused());
+
+        chip8.step_over().unwrap();
+        assert!(!chip8.is_paused());
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), 0x202);
+        assert_eq!(chip8.register(0x0), 4);
+        assert!(!chip8.step_out());
+
+        chip8.run_to(0x20C);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), 0x20C);
+        assert_eq!(
+            chip8.debugger_mut().take_stop_reason(),
+            Some(StopReason::RunToReached(0x20C))
+        );
+        assert_eq!(chip8.debugger_mut().take_stop_reason(), None);
+        assert_eq!(
+            chip8.debugger().stop_reason(),
+            Some(StopReason::RunToReached(0x20C))
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut chip8 = Chip8::new();
+        let program = [
+            0xA3, 0x00, // LD I, 0x300
+            0x63, 0x10, // LD V3, 0x10
+            0xF2, 0x55, // LD [I], V2
+            0xF1, 0x65, // LD V1, [I]
+            0xA3, 0x10, // LD I, 0x310
+            0x12, 0x0A, // JP 0x20A
+        ];
+        chip8.load_program_bytes(&program).unwrap();
+        let watchpoints = [
+            Watchpoint::Memory {
+                access: Access::Write,
+                start: 0x302,
+                end: 0x3FF,
+            },
+            Watchpoint::Memory {
+                access: Access::Read,
+                start: 0x2FF,
+                end: 0x303,
+            },
+            Watchpoint::Register {
+                register: 0x3,
+                comparison: Comparison::GreaterOrEqual,
+                value: 0x10,
+            },
+            Watchpoint::Index {
+                start: 0x30F,
+                end: 0x31F,
+            },
+        ];
+        for &watchpoint in watchpoints.iter() {
+            chip8.debugger_mut().add_watchpoint(watchpoint);
+        }
+
+        // Watchpoints stop after the instruction that hit them
+        for &(pc, watchpoint) in [(0x204, 2), (0x206, 0), (0x208, 1), (0x20A, 3)].iter() {
+            chip8.resume();
+            chip8.run_frame().unwrap();
+            assert_eq!(chip8.pc(), pc);
+            assert_eq!(
+                chip8.debugger().stop_reason(),
+                Some(StopReason::Watchpoint(watchpoints[watchpoint]))
+            );
+        }
+
+        chip8.resume();
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_paused());
+
+        assert_eq!(
+            chip8.debugger_mut().remove_watchpoint(3),
+            Some(watchpoints[3])
+        );
+        assert_eq!(chip8.debugger_mut().remove_watchpoint(3), None);
+        chip8.debugger_mut().clear_watchpoints();
+        chip8
+            .debugger_mut()
+            .add_watchpoint(Watchpoint::RegisterChange(0x3));
+        chip8.set_register(0x3, 0);
+        chip8.set_pc(0x202);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), 0x204);
+        assert_eq!(
+            chip8.debugger().stop_reason(),
+            Some(StopReason::Watchpoint(Watchpoint::RegisterChange(0x3)))
+        );
+    }
+
+    #[test]
+    fn test_step_into_watchpoint() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD I, 0x300 / 0x202: LD [I], V0 / 0x204: LD [I], V0
+        chip8
+            .load_program_bytes(&[0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x55])
+            .unwrap();
+        let watchpoint = Watchpoint::Memory {
+            access: Access::Write,
+            start: 0x300,
+            end: 0x300,
+        };
+        chip8.debugger_mut().add_watchpoint(watchpoint);
+        chip8.pause();
+
+        chip8.step_into().unwrap();
+        assert_eq!(chip8.debugger().stop_reason(), Some(StopReason::Step));
+        chip8.step_into().unwrap();
+        assert_eq!(chip8.pc(), 0x204);
+        assert_eq!(
+            chip8.debugger().stop_reason(),
+            Some(StopReason::Watchpoint(watchpoint))
+        );
+    }
+
+    #[test]
+    fn test_stop_on_error() {
+        let mut chip8 = Chip8::new();
+        chip8.load_program_bytes(&[0x00, 0xEE]).unwrap();
+
+        assert!(chip8.run_frame().is_err());
+        assert!(chip8.is_paused());
+        assert_eq!(
+            chip8.debugger().stop_reason(),
+            Some(StopReason::Error(EmulationError::StackUnderflow))
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use super::ExecutionState;
+
+/// Pitch that plays the XO-CHIP pattern at 4000 samples per second
+const DEFAULT_PITCH: u8 = 64;
+
+/// XO-CHIP audio state: a 128 samples 1-bit pattern played at a frequency set by the pitch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmulatedAudio {
+    /// Pattern loaded by F002. None until the program loads one, meaning a plain beep
+    pub pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+}
+
+impl Default for EmulatedAudio {
+    fn default() -> Self {
+        EmulatedAudio {
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+impl EmulatedAudio {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Copies the 16 bytes of pattern into the audio pattern buffer
+    pub fn load_pattern(&mut self, pattern: &[u8]) -> ExecutionState {
+        let mut buffer = [0; 16];
+        buffer.copy_from_slice(&pattern[..16]);
+        self.pattern = Some(buffer);
+
+        ExecutionState::Continue
+    }
+
+    pub fn set_pitch(&mut self, vx: u8) -> ExecutionState {
+        self.pitch = vx;
+
+        ExecutionState::Continue
+    }
+
+    /// Rate at which the pattern bits are played, in bits per second
+    ///
+    /// 4000 * 2 ^ ((pitch - 64) / 48)
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio() {
+        let mut audio = EmulatedAudio::new();
+        assert_eq!(audio.pattern, None);
+        assert!((audio.playback_rate() - 4000.0).abs() < 1e-9);
+
+        audio.set_pitch(112);
+        assert!((audio.playback_rate() - 8000.0).abs() < 1e-9);
+
+        let pattern: Vec<u8> = (0..20).collect();
+        audio.load_pattern(&pattern);
+        assert_eq!(audio.pattern.unwrap()[15], 15);
+    }
+}
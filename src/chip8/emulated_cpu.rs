@@ -2,7 +2,7 @@
 
 use super::ExecutionState;
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, PartialEq, Clone)]
 pub struct EmulatedCpu {
     ///Emulates 16 8-bit register. register[0xF] should only be used internally as flag
     pub register: [u8; 16],
@@ -75,7 +75,7 @@ impl EmulatedCpu {
     pub fn register_carry_add(&mut self, x: u8, y: u8) -> ExecutionState {
         let sum = self.register[x as usize] as u16 + self.register[y as usize] as u16;
         self.register[0xF] = (sum > 0xFF) as u8;
-        self.register[x as usize] = sum as u8 & 0x00FF;
+        self.register[x as usize] = sum as u8;
 
         ExecutionState::Continue
     }
@@ -110,26 +110,32 @@ impl EmulatedCpu {
         ExecutionState::Continue
     }
 
-    /// Shifts register[x] bits 1pos to the right
+    /// Shifts register[y] bits 1pos to the right
+    ///
+    /// Result stored on register[x]. Pass y = x to shift register[x] in place
     ///
     /// If the least significant bit is 1, register[0xF] is set
     ///
     /// Returns ExecutionState::Continue
-    pub fn register_shr(&mut self, x: u8) -> ExecutionState {
-        self.register[0xF] = self.register[x as usize] & 0b00000001;
-        self.register[x as usize] >>= 1;
+    pub fn register_shr(&mut self, x: u8, y: u8) -> ExecutionState {
+        let flag = self.register[y as usize] & 0b00000001;
+        self.register[x as usize] = self.register[y as usize] >> 1;
+        self.register[0xF] = flag;
 
         ExecutionState::Continue
     }
 
-    /// Shifts register[x] bits 1pos to the left
+    /// Shifts register[y] bits 1pos to the left
+    ///
+    /// Result stored on register[x]. Pass y = x to shift register[x] in place
     ///
     /// If the most significant bit is 1, register[0xF] is set
     ///
     /// Returns ExecutionState::Continue
-    pub fn register_shl(&mut self, x: u8) -> ExecutionState {
-        self.register[0xF] = (self.register[x as usize] & 0b10000000) >> 7;
-        self.register[x as usize] <<= 1;
+    pub fn register_shl(&mut self, x: u8, y: u8) -> ExecutionState {
+        let flag = (self.register[y as usize] & 0b10000000) >> 7;
+        self.register[x as usize] = self.register[y as usize] << 1;
+        self.register[0xF] = flag;
 
         ExecutionState::Continue
     }
@@ -248,25 +254,36 @@ mod tests {
         assert_eq!(cpu.register[0x1], 0b00111001);
 
         cpu.set_register(0x4, 0b00110000);
-        cpu.register_shr(0x4);
+        cpu.register_shr(0x4, 0x4);
         assert_eq!(cpu.register[0x4], 0b00011000);
         assert_eq!(cpu.register[0xF], 0);
 
         cpu.set_register(0x4, 0b10000011);
-        cpu.register_shr(0x4);
+        cpu.register_shr(0x4, 0x4);
         assert_eq!(cpu.register[0x4], 0b01000001);
         assert_eq!(cpu.register[0xF], 1);
 
         cpu.set_register(0x4, 0b00110000);
-        cpu.register_shl(0x4);
+        cpu.register_shl(0x4, 0x4);
         assert_eq!(cpu.register[0x4], 0b01100000);
         assert_eq!(cpu.register[0xF], 0);
 
         cpu.set_register(0x4, 0b10000011);
-        cpu.register_shl(0x4);
+        cpu.register_shl(0x4, 0x4);
         assert_eq!(cpu.register[0x4], 0b00000110);
         assert_eq!(cpu.register[0xF], 1);
 
+        cpu.set_register(0x3, 0b00000110);
+        cpu.register_shr(0x4, 0x3);
+        assert_eq!(cpu.register[0x4], 0b00000011);
+        assert_eq!(cpu.register[0x3], 0b00000110);
+        assert_eq!(cpu.register[0xF], 0);
+
+        cpu.set_register(0x3, 0b10000001);
+        cpu.register_shl(0x4, 0x3);
+        assert_eq!(cpu.register[0x4], 0b00000010);
+        assert_eq!(cpu.register[0xF], 1);
+
         cpu.set_register(0x5, 0b00000111);
         cpu.set_register(0x6, 0b00000111);
         cpu.register_random_and(0x5, 0b00111100);
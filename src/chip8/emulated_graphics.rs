@@ -1,15 +1,25 @@
 use super::ExecutionState;
 
-const WINDOW_SIZE: (usize, usize) = (64, 32);
+const LORES_SIZE: (usize, usize) = (64, 32);
+const HIRES_SIZE: (usize, usize) = (128, 64);
 
+#[derive(Clone)]
 pub struct EmulatedGraphics {
-    pub display: [u8; WINDOW_SIZE.0 * WINDOW_SIZE.1],
+    /// Big enough for the hires mode. Only the first width() * height() pixels are in use
+    ///
+    /// Each pixel holds one bit per plane, so values go from 0 to 3
+    pub display: [u8; HIRES_SIZE.0 * HIRES_SIZE.1],
+    pub hires: bool,
+    /// Bitmask of the XO-CHIP planes affected by drawing, clearing and scrolling
+    pub planes: u8,
 }
 
 impl Default for EmulatedGraphics {
     fn default() -> EmulatedGraphics {
         EmulatedGraphics {
-            display: [0; WINDOW_SIZE.0 * WINDOW_SIZE.1],
+            display: [0; HIRES_SIZE.0 * HIRES_SIZE.1],
+            hires: false,
+            planes: 0b01,
         }
     }
 }
@@ -19,32 +29,197 @@ impl EmulatedGraphics {
         Default::default()
     }
 
+    /// Current resolution, 64x32 on lores mode and 128x64 on hires mode
+    pub fn size(&self) -> (usize, usize) {
+        if self.hires {
+            HIRES_SIZE
+        } else {
+            LORES_SIZE
+        }
+    }
+
+    /// Pixels currently on screen, one byte per pixel, row-major
+    pub fn pixels(&self) -> &[u8] {
+        let (width, height) = self.size();
+        &self.display[..width * height]
+    }
+
+    /// Number of selected planes, which is how many sprites a single draw consumes
+    pub fn plane_count(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
+    /// Selects the planes affected by the next operations. Only the lowest 2 bits are used
+    pub fn select_planes(&mut self, planes: u8) -> ExecutionState {
+        self.planes = planes & 0b11;
+
+        ExecutionState::Continue
+    }
+
+    /// Clears the selected planes
     pub fn clear_display(&mut self) -> ExecutionState {
+        let planes = self.planes;
+        self.display.iter_mut().for_each(|byte| *byte &= !planes);
+
+        ExecutionState::Continue
+    }
+
+    /// Switches between 64x32 and 128x64 modes. The whole display is cleared
+    pub fn set_hires(&mut self, hires: bool) -> ExecutionState {
+        self.hires = hires;
         self.display.iter_mut().for_each(|byte| *byte = 0);
 
         ExecutionState::Continue
     }
 
-    pub fn draw_sprite(&mut self, x_pos: u8, y_pos: u8, n: u8, sprite: &[u8]) -> bool {
+    /// Moves the selected planes n lines down, the top lines become blank
+    pub fn scroll_down(&mut self, n: u8) -> ExecutionState {
+        let (width, height) = self.size();
+        let n = n as usize;
+
+        for y in (0..height).rev() {
+            self.move_line(y.checked_sub(n), y, width);
+        }
+
+        ExecutionState::Continue
+    }
+
+    /// Moves the selected planes n lines up, the bottom lines become blank
+    pub fn scroll_up(&mut self, n: u8) -> ExecutionState {
+        let (width, height) = self.size();
+        let n = n as usize;
+
+        for y in 0..height {
+            let source = Some(y + n).filter(|&source| source < height);
+            self.move_line(source, y, width);
+        }
+
+        ExecutionState::Continue
+    }
+
+    /// Moves the selected planes 4 pixels to the right, the leftmost pixels become blank
+    pub fn scroll_right(&mut self) -> ExecutionState {
+        let (width, height) = self.size();
+
+        for y in 0..height {
+            for x in (0..width).rev() {
+                let source = x.checked_sub(4).map(|source| width * y + source);
+                self.move_pixel(source, width * y + x);
+            }
+        }
+
+        ExecutionState::Continue
+    }
+
+    /// Moves the selected planes 4 pixels to the left, the rightmost pixels become blank
+    pub fn scroll_left(&mut self) -> ExecutionState {
+        let (width, height) = self.size();
+
+        for y in 0..height {
+            for x in 0..width {
+                let source = Some(x + 4)
+                    .filter(|&source| source < width)
+                    .map(|source| width * y + source);
+                self.move_pixel(source, width * y + x);
+            }
+        }
+
+        ExecutionState::Continue
+    }
+
+    fn move_line(&mut self, source: Option<usize>, destination: usize, width: usize) {
+        for x in 0..width {
+            self.move_pixel(source.map(|y| width * y + x), width * destination + x);
+        }
+    }
+
+    /// Copies the selected planes of pixel source into destination, or blanks them if None
+    fn move_pixel(&mut self, source: Option<usize>, destination: usize) {
+        let value = source.map_or(0, |source| self.display[source]) & self.planes;
+
+        self.display[destination] = (self.display[destination] & !self.planes) | value;
+    }
+
+    /// XORs the n lines of sprite into the display, starting at (x_pos, y_pos)
+    ///
+    /// The starting position always wraps around the display. Pixels past the edges are
+    /// discarded when clip is set, otherwise they wrap around to the other side
+    ///
+    /// With more than one plane selected, sprite holds n lines for each plane, one after
+    /// the other
+    ///
+    /// Returns true if any set pixel was unset
+    pub fn draw_sprite(&mut self, x_pos: u8, y_pos: u8, n: u8, sprite: &[u8], clip: bool) -> bool {
+        let mut colision_flag = false;
+
+        for (plane, sprite) in self.selected_planes().zip(sprite.chunks(n.max(1) as usize)) {
+            let lines = sprite.iter().map(|&line| (line as u16) << 8);
+            colision_flag |= self.draw_lines(x_pos, y_pos, lines, plane, clip);
+        }
+        colision_flag
+    }
+
+    /// Same as draw_sprite(), for the SUPER-CHIP 16x16 sprites stored as 32 bytes per plane
+    pub fn draw_large_sprite(&mut self, x_pos: u8, y_pos: u8, sprite: &[u8], clip: bool) -> bool {
         let mut colision_flag = false;
 
-        for byte in 0..n {
-            let current_line = sprite[byte as usize];
-            let y_pos = (y_pos + byte) as usize % 32;
+        for (plane, sprite) in self.selected_planes().zip(sprite.chunks(32)) {
+            let lines = sprite
+                .chunks(2)
+                .map(|line| (line[0] as u16) << 8 | line[1] as u16);
+            colision_flag |= self.draw_lines(x_pos, y_pos, lines, plane, clip);
+        }
+        colision_flag
+    }
 
-            for bit in 0..8 {
-                let current_pixel = (current_line & (0b10000000 >> bit)) >> (7 - bit);
-                let x_pos = (x_pos + bit) as usize % 64;
+    /// Bit of each selected plane, in drawing order
+    fn selected_planes(&self) -> impl Iterator<Item = u8> {
+        let planes = self.planes;
+        [0b01, 0b10]
+            .iter()
+            .copied()
+            .filter(move |plane| planes & plane != 0)
+    }
+
+    /// Draws up to 16 pixels wide lines into plane, the most significant bit being the
+    /// leftmost pixel
+    fn draw_lines<I: Iterator<Item = u16>>(
+        &mut self,
+        x_pos: u8,
+        y_pos: u8,
+        lines: I,
+        plane: u8,
+        clip: bool,
+    ) -> bool {
+        let (width, height) = self.size();
+        let x_start = x_pos as usize % width;
+        let y_start = y_pos as usize % height;
+        let mut colision_flag = false;
+
+        for (line, current_line) in lines.enumerate() {
+            let y_pos = y_start + line;
+            if clip && y_pos >= height {
+                break;
+            }
+            let y_pos = y_pos % height;
+
+            for bit in 0..16 {
+                let current_pixel = current_line & (0x8000 >> bit);
+                let x_pos = x_start + bit;
+                if clip && x_pos >= width {
+                    break;
+                }
+                let x_pos = x_pos % width;
 
                 if current_pixel != 0 {
-                    let ref mut actual_display_pixel = self.display[WINDOW_SIZE.0 * y_pos + x_pos];
+                    let actual_display_pixel = &mut self.display[width * y_pos + x_pos];
 
-                    if *actual_display_pixel == 1 {
+                    if *actual_display_pixel & plane != 0 {
                         //The actual pixel position beeing set is already set
                         colision_flag = true;
                     }
 
-                    *actual_display_pixel ^= 1;
+                    *actual_display_pixel ^= plane;
                 }
             }
         }
@@ -61,6 +236,8 @@ mod tests {
         let graphics = EmulatedGraphics::new();
 
         assert!(graphics.display.iter().all(|&byte| byte == 0));
+        assert_eq!(graphics.size(), (64, 32));
+        assert_eq!(graphics.pixels().len(), 64 * 32);
     }
 
     #[test]
@@ -75,9 +252,116 @@ mod tests {
             .iter_mut()
             .step_by(3)
             .for_each(|byte| *byte = 1);
-        assert!(graphics.display.iter().any(|&byte| byte == 1));
+        assert!(graphics.display.contains(&1));
 
         graphics.clear_display();
         assert!(graphics.display.iter().all(|&byte| byte == 0));
     }
+
+    #[test]
+    fn test_draw_sprite() {
+        let mut graphics = EmulatedGraphics::new();
+        let sprite = [0b11000000, 0b11000000];
+
+        assert!(!graphics.draw_sprite(0, 0, 2, &sprite, true));
+        assert_eq!(graphics.display[0..3], [1, 1, 0]);
+        assert_eq!(graphics.display[64..67], [1, 1, 0]);
+
+        assert!(graphics.draw_sprite(1, 1, 1, &sprite, true));
+        assert_eq!(graphics.display[64..67], [1, 0, 1]);
+    }
+
+    #[test]
+    fn test_clip_and_wrap() {
+        let mut graphics = EmulatedGraphics::new();
+        let sprite = [0b11000000, 0b11000000];
+
+        // Starting position wraps, (65, 33) is the same as (1, 1)
+        graphics.draw_sprite(65, 33, 1, &sprite, true);
+        assert_eq!(graphics.display[65], 1);
+        graphics.clear_display();
+
+        graphics.draw_sprite(63, 31, 2, &sprite, true);
+        assert_eq!(
+            graphics.display.iter().filter(|&&byte| byte == 1).count(),
+            1
+        );
+        assert_eq!(graphics.display[64 * 31 + 63], 1);
+        graphics.clear_display();
+
+        graphics.draw_sprite(63, 31, 2, &sprite, false);
+        assert_eq!(
+            graphics.display.iter().filter(|&&byte| byte == 1).count(),
+            4
+        );
+        assert_eq!(graphics.display[0], 1);
+        assert_eq!(graphics.display[64 * 31], 1);
+        assert_eq!(graphics.display[63], 1);
+    }
+
+    #[test]
+    fn test_hires_and_large_sprites() {
+        let mut graphics = EmulatedGraphics::new();
+        graphics.draw_sprite(0, 0, 1, &[0xFF], true);
+
+        graphics.set_hires(true);
+        assert_eq!(graphics.size(), (128, 64));
+        assert!(graphics.pixels().iter().all(|&byte| byte == 0));
+
+        let sprite = [0xFF; 32];
+        assert!(!graphics.draw_large_sprite(100, 10, &sprite, true));
+        assert_eq!(
+            graphics.pixels().iter().filter(|&&byte| byte == 1).count(),
+            256
+        );
+        assert_eq!(graphics.display[128 * 10 + 100], 1);
+        assert_eq!(graphics.display[128 * 25 + 115], 1);
+        assert!(graphics.draw_large_sprite(115, 25, &sprite, true));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut graphics = EmulatedGraphics::new();
+        graphics.draw_sprite(0, 0, 1, &[0b10000000], true);
+
+        graphics.scroll_down(3);
+        assert_eq!(graphics.display[0], 0);
+        assert_eq!(graphics.display[64 * 3], 1);
+
+        graphics.scroll_right();
+        assert_eq!(graphics.display[64 * 3], 0);
+        assert_eq!(graphics.display[64 * 3 + 4], 1);
+
+        graphics.scroll_left();
+        graphics.scroll_left();
+        assert!(graphics.pixels().iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut graphics = EmulatedGraphics::new();
+
+        graphics.select_planes(0b11);
+        assert_eq!(graphics.plane_count(), 2);
+        graphics.draw_sprite(0, 0, 1, &[0b11000000, 0b10000000], true);
+        assert_eq!(graphics.display[0..3], [3, 1, 0]);
+
+        graphics.select_planes(0b10);
+        assert!(graphics.draw_sprite(0, 0, 1, &[0b11000000], true));
+        assert_eq!(graphics.display[0..3], [1, 3, 0]);
+
+        graphics.scroll_down(1);
+        assert_eq!(graphics.display[0..3], [1, 1, 0]);
+        assert_eq!(graphics.display[64..67], [0, 2, 0]);
+
+        graphics.scroll_up(1);
+        assert_eq!(graphics.display[0..3], [1, 3, 0]);
+
+        graphics.clear_display();
+        assert_eq!(graphics.display[0..3], [1, 1, 0]);
+
+        graphics.select_planes(0);
+        assert!(!graphics.draw_sprite(0, 0, 1, &[], true));
+        assert_eq!(graphics.display[0..3], [1, 1, 0]);
+    }
 }
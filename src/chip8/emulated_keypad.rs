@@ -1,4 +1,4 @@
-use super::ExecutionState;
+use super::{EmulationError, ExecutionState};
 
 #[derive(Debug, PartialEq, Default)]
 pub struct EmulatedKeypad {
@@ -11,57 +11,40 @@ impl EmulatedKeypad {
     }
 
     /// Set the corresponding key value to 1
-    pub fn press_key(&mut self, key: u8) {
-        if key >= 16 {
-            panic!(
-                "Called press_key() with key = {} when value must be between 0 and 15",
-                key
-            );
-        }
-
-        let key = key as usize;
+    ///
+    /// Returns EmulationError::InvalidKey if key >= 16
+    pub fn press_key(&mut self, key: u8) -> Result<(), EmulationError> {
+        let key = Self::validate_key(key)?;
         self.keypad[key] = 1;
+
+        Ok(())
     }
 
     /// Set the corresponding key value to 0
-    pub fn release_key(&mut self, key: u8) {
-        if key >= 16 {
-            panic!(
-                "Called release_key() with key = {} when value must be between 0 and 15",
-                key
-            );
-        }
-        let key = key as usize;
+    ///
+    /// Returns EmulationError::InvalidKey if key >= 16
+    pub fn release_key(&mut self, key: u8) -> Result<(), EmulationError> {
+        let key = Self::validate_key(key)?;
         self.keypad[key] = 0;
+
+        Ok(())
     }
 
-    pub fn skip_if_pressed(&self, key: u8) -> ExecutionState {
-        if key >= 16 {
-            panic!(
-                "Called skip_if_pressed() with key = {} when value must be between 0 and 15",
-                key
-            );
-        }
-        let key = key as usize;
+    pub fn skip_if_pressed(&self, key: u8) -> Result<ExecutionState, EmulationError> {
+        let key = Self::validate_key(key)?;
         if self.keypad[key] == 1 {
-            ExecutionState::Skip
+            Ok(ExecutionState::Skip)
         } else {
-            ExecutionState::Continue
+            Ok(ExecutionState::Continue)
         }
     }
 
-    pub fn skip_if_released(&self, key: u8) -> ExecutionState {
-        if key >= 16 {
-            panic!(
-                "Called skip_if_released() with key = {} when value must be between 0 and 15",
-                key
-            );
-        }
-        let key = key as usize;
+    pub fn skip_if_released(&self, key: u8) -> Result<ExecutionState, EmulationError> {
+        let key = Self::validate_key(key)?;
         if self.keypad[key] == 0 {
-            ExecutionState::Skip
+            Ok(ExecutionState::Skip)
         } else {
-            ExecutionState::Continue
+            Ok(ExecutionState::Continue)
         }
     }
 
@@ -74,6 +57,14 @@ impl EmulatedKeypad {
             ExecutionState::Hold
         }
     }
+
+    fn validate_key(key: u8) -> Result<usize, EmulationError> {
+        if key >= 16 {
+            Err(EmulationError::InvalidKey(key))
+        } else {
+            Ok(key as usize)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -90,26 +81,32 @@ mod tests {
     fn test_press_release() {
         let mut keypad = EmulatedKeypad::new();
 
-        (0..=15).into_iter().for_each(|key| keypad.press_key(key));
+        (0..=15).for_each(|key| keypad.press_key(key).unwrap());
         keypad.keypad.iter().for_each(|&key| assert_eq!(key, 1));
 
-        (0..=15).into_iter().for_each(|key| keypad.release_key(key));
+        (0..=15).for_each(|key| keypad.release_key(key).unwrap());
         keypad.keypad.iter().for_each(|&key| assert_eq!(key, 0));
     }
 
     #[test]
-    #[should_panic]
-    fn test_panic_on_press() {
+    fn test_error_on_press() {
         let mut keypad = EmulatedKeypad::new();
 
-        keypad.press_key(16);
+        assert_eq!(keypad.press_key(16), Err(EmulationError::InvalidKey(16)));
     }
 
     #[test]
-    #[should_panic]
-    fn test_panic_on_release() {
+    fn test_error_on_release() {
         let mut keypad = EmulatedKeypad::new();
 
-        keypad.release_key(16);
+        assert_eq!(keypad.release_key(16), Err(EmulationError::InvalidKey(16)));
+    }
+
+    #[test]
+    fn test_error_on_skip() {
+        let keypad = EmulatedKeypad::new();
+
+        assert!(keypad.skip_if_pressed(0x10).is_err());
+        assert!(keypad.skip_if_released(0xFF).is_err());
     }
 }
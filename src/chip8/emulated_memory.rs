@@ -1,8 +1,9 @@
-use super::ExecutionState;
+use super::{EmulationError, ExecutionState};
 
-use std::convert::TryInto;
+pub const MEM_SIZE: usize = 4096;
 
-const MEM_SIZE: usize = 4096;
+/// XO-CHIP memory size, fully addressable with F000 NNNN
+pub const EXTENDED_MEM_SIZE: usize = 0x10000;
 
 const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0,
@@ -14,8 +15,25 @@ const FONT_SET: [u8; 80] = [
 
 const FONT_SET_START: usize = 0x50;
 
+/// SUPER-CHIP 8x10 hex digits, used by FX30
+const BIG_FONT_SET: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, 0x18, 0x38, 0x58, 0x18, 0x18, 0x18,
+    0x18, 0x18, 0x18, 0x3C, 0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, 0x3C, 0x7E,
+    0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, 0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF,
+    0x06, 0x06, 0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, 0x3E, 0x7C, 0xE0, 0xC0,
+    0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, 0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, 0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F,
+    0x03, 0x03, 0x3E, 0x7C, 0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xFC, 0xFE,
+    0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, 0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3,
+    0x7E, 0x3C, 0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, 0xFF, 0xFF, 0xC0, 0xC0,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0,
+];
+
+const BIG_FONT_SET_START: usize = FONT_SET_START + 80;
+
+#[derive(Clone)]
 pub struct EmulatedMemory {
-    pub mem_array: [u8; 4096],
+    pub mem_array: Vec<u8>,
     pub index: usize,
     pub stack: [u16; 16],
     pub stack_pointer: usize,
@@ -27,10 +45,17 @@ impl EmulatedMemory {
         Self::default()
     }
 
-    /// Initializes the EmulatedMemory with the initial values, loading the FONT_SET to the corret pos
+    /// Initializes the EmulatedMemory with the initial values, loading the FONT_SET and
+    /// BIG_FONT_SET to the corret pos
     pub fn default() -> Self {
-        let mut mem_array: [u8; MEM_SIZE] = [0; MEM_SIZE];
+        Self::with_size(MEM_SIZE)
+    }
+
+    /// Same as default(), with size bytes of memory instead of MEM_SIZE
+    pub fn with_size(size: usize) -> Self {
+        let mut mem_array = vec![0; size];
         mem_array[FONT_SET_START..FONT_SET_START + 80].clone_from_slice(&FONT_SET[..]);
+        mem_array[BIG_FONT_SET_START..BIG_FONT_SET_START + 160].clone_from_slice(&BIG_FONT_SET[..]);
 
         EmulatedMemory {
             mem_array,
@@ -49,24 +74,70 @@ impl EmulatedMemory {
         ExecutionState::Continue
     }
 
+    /// Checks that the len bytes starting at address are all inside mem_array
+    ///
+    /// Returns EmulationError::MemoryOutOfBounds with the first invalid address otherwise
+    pub fn check_bounds(&self, address: usize, len: usize) -> Result<(), EmulationError> {
+        if address
+            .checked_add(len)
+            .is_none_or(|end| end > self.mem_array.len())
+        {
+            Err(EmulationError::MemoryOutOfBounds {
+                address: address.max(self.mem_array.len()),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Points index to the 5 lines sprite of the hex digit vx
+    ///
+    /// Only the lowest nibble of vx is considered
+    pub fn set_index_font(&mut self, vx: u8) -> ExecutionState {
+        self.index = FONT_SET_START + (vx & 0xF) as usize * 5;
+
+        ExecutionState::Continue
+    }
+
+    /// Points index to the 10 lines SUPER-CHIP sprite of the hex digit vx
+    ///
+    /// Only the lowest nibble of vx is considered
+    pub fn set_index_big_font(&mut self, vx: u8) -> ExecutionState {
+        self.index = BIG_FONT_SET_START + (vx & 0xF) as usize * 10;
+
+        ExecutionState::Continue
+    }
+
     /// Stores the current address at the stack and points one position above it
     ///
-    /// Returns ExecutionState::JumpTo(nnn)
-    pub fn call_subroutine(&mut self, nnn: usize, pc: usize) -> ExecutionState {
-        self.stack[self.stack_pointer] = pc.try_into().expect("Fail to convert pc to u8");
+    /// Returns ExecutionState::JumpTo(nnn), or EmulationError::StackOverflow if the stack is full
+    pub fn call_subroutine(
+        &mut self,
+        nnn: usize,
+        pc: usize,
+    ) -> Result<ExecutionState, EmulationError> {
+        if self.stack_pointer >= self.stack.len() {
+            return Err(EmulationError::StackOverflow);
+        }
+
+        self.stack[self.stack_pointer] = pc as u16;
         self.stack_pointer += 1;
 
-        ExecutionState::JumpTo(nnn)
+        Ok(ExecutionState::JumpTo(nnn))
     }
 
     /// Decrements the stack pointer, acessing the previous address stored in it
     ///
-    /// Returns ExecutionState::ReturnTo(pc)
-    pub fn return_from_subroutine(&mut self) -> ExecutionState {
+    /// Returns ExecutionState::ReturnTo(pc), or EmulationError::StackUnderflow if the stack is empty
+    pub fn return_from_subroutine(&mut self) -> Result<ExecutionState, EmulationError> {
+        if self.stack_pointer == 0 {
+            return Err(EmulationError::StackUnderflow);
+        }
+
         self.stack_pointer -= 1;
         let pc = self.stack[self.stack_pointer];
 
-        ExecutionState::ReturnTo(pc as usize)
+        Ok(ExecutionState::ReturnTo(pc as usize))
     }
 
     /// Returns ExecutionState::JumpTo(nnn)
@@ -88,12 +159,14 @@ impl EmulatedMemory {
     /// Breaks x into Hundreds / Tens / Units
     ///
     /// Stores at positions I, I+1, I+2 of the memory respectively
-    pub fn memory_store_bcd(&mut self, x: u8) -> ExecutionState {
-        self.mem_array[self.index as usize] = x / 100;
-        self.mem_array[self.index as usize + 1] = (x / 10) % 10;
-        self.mem_array[self.index as usize + 2] = x % 10;
+    pub fn memory_store_bcd(&mut self, x: u8) -> Result<ExecutionState, EmulationError> {
+        self.check_bounds(self.index, 3)?;
 
-        ExecutionState::Continue
+        self.mem_array[self.index] = x / 100;
+        self.mem_array[self.index + 1] = (x / 10) % 10;
+        self.mem_array[self.index + 2] = x % 10;
+
+        Ok(ExecutionState::Continue)
     }
 }
 
@@ -105,8 +178,10 @@ mod tests {
         let memory = EmulatedMemory::new();
 
         memory.mem_array.iter().enumerate().for_each(|(i, &val)| {
-            if i >= FONT_SET_START && i < FONT_SET_START + 80 {
+            if (FONT_SET_START..FONT_SET_START + 80).contains(&i) {
                 assert_eq!(val, FONT_SET[i - FONT_SET_START]);
+            } else if (BIG_FONT_SET_START..BIG_FONT_SET_START + 160).contains(&i) {
+                assert_eq!(val, BIG_FONT_SET[i - BIG_FONT_SET_START]);
             } else {
                 assert_eq!(val, 0);
             }
@@ -133,6 +208,17 @@ mod tests {
         memory.set_index(0xFFFF);
         memory.index_add(0xF);
         assert_eq!(memory.index, 0xE);
+
+        memory.set_index_font(0xA);
+        assert_eq!(memory.index, FONT_SET_START + 50);
+        assert_eq!(memory.mem_array[memory.index], 0xF0);
+
+        memory.set_index_big_font(0x13);
+        assert_eq!(memory.index, BIG_FONT_SET_START + 30);
+        assert_eq!(
+            memory.mem_array[memory.index..memory.index + 2],
+            [0x3C, 0x7E]
+        );
     }
 
     #[test]
@@ -144,7 +230,7 @@ mod tests {
         }
 
         let mut pc = 0x200;
-        let state = memory.call_subroutine(0xABC, pc);
+        let state = memory.call_subroutine(0xABC, pc).unwrap();
 
         assert_eq!(memory.stack[0], 0x200);
         assert_eq!(memory.stack_pointer, 1);
@@ -153,7 +239,7 @@ mod tests {
             assert_eq!(pc, 0xABC);
         }
 
-        let state = memory.call_subroutine(0xFCE, pc);
+        let state = memory.call_subroutine(0xFCE, pc).unwrap();
 
         assert_eq!(memory.stack[0], 0x200);
         assert_eq!(memory.stack[1], 0xABC);
@@ -163,7 +249,7 @@ mod tests {
             assert_eq!(pc, 0xFCE);
         }
 
-        let state = memory.return_from_subroutine();
+        let state = memory.return_from_subroutine().unwrap();
         assert_eq!(memory.stack[0], 0x200);
         assert_eq!(memory.stack[1], 0xABC);
         assert_eq!(memory.stack_pointer, 1);
@@ -172,7 +258,7 @@ mod tests {
             assert_eq!(pc, 0xABC);
         }
 
-        let state = memory.return_from_subroutine();
+        let state = memory.return_from_subroutine().unwrap();
         assert_eq!(memory.stack[0], 0x200);
         assert_eq!(memory.stack_pointer, 0);
         if let ExecutionState::ReturnTo(addr) = state {
@@ -181,15 +267,44 @@ mod tests {
         }
 
         let number = 159;
-        memory.memory_store_bcd(number);
+        memory.memory_store_bcd(number).unwrap();
         assert_eq!(memory.mem_array[memory.index], 1);
         assert_eq!(memory.mem_array[memory.index + 1], 5);
         assert_eq!(memory.mem_array[memory.index + 2], 9);
 
         let number = 255;
-        memory.memory_store_bcd(number);
+        memory.memory_store_bcd(number).unwrap();
         assert_eq!(memory.mem_array[memory.index], 2);
         assert_eq!(memory.mem_array[memory.index + 1], 5);
         assert_eq!(memory.mem_array[memory.index + 2], 5);
     }
+
+    #[test]
+    fn test_memory_errors() {
+        let mut memory = EmulatedMemory::new();
+
+        assert_eq!(
+            memory.return_from_subroutine().err(),
+            Some(EmulationError::StackUnderflow)
+        );
+
+        (0..16).for_each(|_| {
+            memory.call_subroutine(0x300, 0x200).unwrap();
+        });
+        assert_eq!(
+            memory.call_subroutine(0x300, 0x200).err(),
+            Some(EmulationError::StackOverflow)
+        );
+
+        memory.set_index(MEM_SIZE - 2);
+        assert_eq!(
+            memory.memory_store_bcd(123).err(),
+            Some(EmulationError::MemoryOutOfBounds { address: MEM_SIZE })
+        );
+        assert!(memory.check_bounds(MEM_SIZE - 3, 3).is_ok());
+
+        let memory = EmulatedMemory::with_size(EXTENDED_MEM_SIZE);
+        assert!(memory.check_bounds(EXTENDED_MEM_SIZE - 3, 3).is_ok());
+        assert_eq!(memory.mem_array[FONT_SET_START], FONT_SET[0]);
+    }
 }
@@ -10,7 +10,7 @@ impl Timer {
         if self.curr_time > 0 {
             self.curr_time -= 1;
         }
-        
+
         *self
     }
 }
@@ -19,6 +19,9 @@ impl Timer {
 pub struct EmulatedTimers {
     sound_timer: Timer,
     delay_timer: Timer,
+    /// Whether the sound timer was non-zero when the last frame ended, so the buzzer
+    /// sounds for as many frames as the value it was set to
+    buzzed: bool,
 }
 
 impl EmulatedTimers {
@@ -27,31 +30,35 @@ impl EmulatedTimers {
     }
 
     pub fn tick(&mut self) -> Self {
+        self.buzzed = self.sound_timer.curr_time > 0;
         self.sound_timer.tick();
         self.delay_timer.tick();
-        
-        if self.sound_timer.curr_time == 1 {
-            println!("BEEP!");
-        } 
-        
+
         *self
     }
-    
+
     pub fn set_delay_timer(&mut self, value: u8) -> ExecutionState {
         self.delay_timer.curr_time = value;
 
         ExecutionState::Continue
     }
-    
+
     pub fn set_sound_timer(&mut self, value: u8) -> ExecutionState {
         self.sound_timer.curr_time = value;
-        
+
         ExecutionState::Continue
     }
-    
+
     pub fn get_delay_timer(&self) -> u8 {
         self.delay_timer.curr_time
     }
-}
 
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer.curr_time
+    }
 
+    /// Whether the buzzer sounds during the frame that just ended
+    pub fn is_buzzing(&self) -> bool {
+        self.buzzed || self.sound_timer.curr_time > 0
+    }
+}
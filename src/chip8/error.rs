@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Reasons why the emulated machine can't keep executing the loaded program
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EmulationError {
+    /// The opcode fetched at address doesn't belong to the instruction set
+    UnknownOpcode { opcode: u16, address: usize },
+    /// A subroutine was called with all 16 stack entries in use
+    StackOverflow,
+    /// A subroutine returned with an empty stack
+    StackUnderflow,
+    /// An instruction tried to access a memory address past the end of memory
+    MemoryOutOfBounds { address: usize },
+    /// A key outside of the 0x0..=0xF range was used
+    InvalidKey(u8),
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulationError::UnknownOpcode { opcode, address } => {
+                write!(
+                    f,
+                    "unknown opcode {:#06x} at address {:#05x}",
+                    opcode, address
+                )
+            }
+            EmulationError::StackOverflow => {
+                write!(f, "stack overflow: more than 16 nested subroutine calls")
+            }
+            EmulationError::StackUnderflow => {
+                write!(
+                    f,
+                    "stack underflow: returned from subroutine with an empty stack"
+                )
+            }
+            EmulationError::MemoryOutOfBounds { address } => {
+                write!(f, "memory access out of bounds at address {:#x}", address)
+            }
+            EmulationError::InvalidKey(key) => {
+                write!(f, "invalid key {}: value must be between 0 and 15", key)
+            }
+        }
+    }
+}
+
+impl Error for EmulationError {}
+
+/// Reasons why a program can't be loaded into memory
+#[derive(Debug)]
+pub enum LoadError {
+    /// The program file couldn't be read
+    Io(io::Error),
+    /// The program has no bytes
+    Empty,
+    /// The program doesn't fit in memory when loaded at address
+    TooLarge {
+        size: usize,
+        max_size: usize,
+        address: usize,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "can't read program: {}", error),
+            LoadError::Empty => write!(f, "program is empty"),
+            LoadError::TooLarge {
+                size,
+                max_size,
+                address,
+            } => write!(
+                f,
+                "program is {} bytes, but only {} bytes fit above address {:#05x}",
+                size, max_size, address
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+/// Reasons why a save state can't be restored
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StateError {
+    /// The data doesn't start with the save state signature
+    NotAState,
+    /// The save state was written by an incompatible version
+    UnsupportedVersion(u8),
+    /// The data ends before the save state does
+    Truncated,
+    /// A field holds a value the emulator can't represent
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "data is not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::InvalidField(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
@@ -1,7 +1,9 @@
-use std::error::Error;
 use std::fs::read;
 use std::path::Path;
 
+mod error;
+pub use error::{EmulationError, LoadError, StateError};
+
 mod emulated_cpu;
 use emulated_cpu::EmulatedCpu;
 
@@ -17,7 +19,34 @@ use emulated_graphics::EmulatedGraphics;
 mod emulated_timers;
 use emulated_timers::EmulatedTimers;
 
-const PROGRAM_START: usize = 0x200;
+mod emulated_audio;
+use emulated_audio::EmulatedAudio;
+
+mod quirks;
+pub use quirks::{IndexIncrement, Platform, Quirks};
+
+mod state;
+pub use state::Snapshot;
+
+mod rewind;
+use rewind::RewindBuffer;
+
+mod debugger;
+pub use debugger::{Access, Comparison, Debugger, StopReason, Watchpoint};
+
+mod symbols;
+pub use symbols::SymbolTable;
+
+mod tracer;
+pub use tracer::{read_binary_trace, TraceEntry, TraceFormat, TracedRegister, Tracer};
+
+/// Default address where programs are loaded and start running
+pub const PROGRAM_START: usize = 0x200;
+
+/// Rate at which the delay and sound timers are decremented, in Hz
+pub const FRAME_RATE: u32 = 60;
+
+const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
 
 pub enum ExecutionState {
     Hold,
@@ -32,56 +61,244 @@ pub struct Chip8 {
     cpu: EmulatedCpu,
     keypad: EmulatedKeypad,
     timers: EmulatedTimers,
-    
-    pub graphics: EmulatedGraphics,
-    pub draw_flag: bool,
+    graphics: EmulatedGraphics,
+    audio: EmulatedAudio,
+    draw_flag: bool,
 
     pc: usize,
+    load_address: usize,
+    instructions_per_frame: usize,
+    platform: Platform,
+    quirks: Quirks,
+    wait_for_vblank: bool,
+    halted: bool,
+    rpl_flags: [u8; 16],
+    rewind: Option<RewindBuffer>,
+    debugger: Debugger,
+    tracer: Option<Tracer>,
+    /// Memory the last instruction read or wrote, as (access, address, length)
+    last_access: Option<(Access, usize, usize)>,
 }
 
-impl Chip8 {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn default() -> Self {
+impl Default for Chip8 {
+    fn default() -> Self {
         Chip8 {
             pc: PROGRAM_START,
+            load_address: PROGRAM_START,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            wait_for_vblank: false,
+            halted: false,
+            rpl_flags: [0; 16],
+            rewind: None,
+            debugger: Debugger::new(),
+            tracer: None,
+            last_access: None,
             memory: EmulatedMemory::new(),
             timers: EmulatedTimers::new(),
             draw_flag: false,
             cpu: EmulatedCpu::new(),
             keypad: EmulatedKeypad::new(),
             graphics: EmulatedGraphics::new(),
+            audio: EmulatedAudio::new(),
+        }
+    }
+}
+
+impl Chip8 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a Chip8 that follows the given quirks instead of the legacy ones
+    ///
+    /// Only the CHIP-8 instruction set is enabled, see with_platform()
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Chip8 {
+            quirks,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a Chip8 emulating platform, with its instruction set and quirks
+    pub fn with_platform(platform: Platform) -> Self {
+        Chip8 {
+            platform,
+            quirks: platform.quirks(),
+            memory: EmulatedMemory::with_size(platform.memory_size()),
+            ..Self::default()
         }
     }
 
-    pub fn load_program<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error>> {
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Switches to platform's instruction set, replacing the quirks with its preset
+    ///
+    /// Memory is resized to platform.memory_size(), keeping its contents
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.quirks = platform.quirks();
+        self.memory.mem_array.resize(platform.memory_size(), 0);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Address where load_program() puts programs, PROGRAM_START unless changed
+    pub fn load_address(&self) -> usize {
+        self.load_address
+    }
+
+    /// Loads the next programs at address, e.g. 0x600 for ETI-660 programs
+    ///
+    /// Takes effect on the next load_program() or reset()
+    pub fn set_load_address(&mut self, address: usize) {
+        self.load_address = address;
+    }
+
+    /// Size in bytes of the largest program that fits in memory above load_address()
+    pub fn max_program_size(&self) -> usize {
+        self.memory
+            .mem_array
+            .len()
+            .saturating_sub(self.load_address)
+    }
+
+    /// Reads the program at path and loads it, see load_program_bytes()
+    pub fn load_program<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoadError> {
         let program_as_binary = read(path)?;
 
-        self.memory.mem_array[PROGRAM_START..program_as_binary.len() + PROGRAM_START]
-            .clone_from_slice(&program_as_binary[..]);
+        self.load_program_bytes(&program_as_binary)
+    }
+
+    /// Resets the machine and copies program to memory at load_address()
+    ///
+    /// Empty programs and programs that don't fit in memory are refused, leaving the
+    /// machine untouched
+    pub fn load_program_bytes(&mut self, program: &[u8]) -> Result<(), LoadError> {
+        if program.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if program.len() > self.max_program_size() {
+            return Err(LoadError::TooLarge {
+                size: program.len(),
+                max_size: self.max_program_size(),
+                address: self.load_address,
+            });
+        }
+
+        self.reset();
+        self.memory.mem_array[self.load_address..self.load_address + program.len()]
+            .clone_from_slice(program);
 
         Ok(())
     }
 
-    pub fn emulate_cycle(&mut self) {
-        let opcode = self.fetch_opcode();
+    /// Brings the machine back to its power-on state, with pc at load_address()
+    ///
+    /// The configuration (platform, quirks, speed and load address) and the RPL flags are kept.
+    /// The rewind history is cleared
+    pub fn reset(&mut self) {
+        let mut rewind = self.rewind.take();
+        if let Some(buffer) = rewind.as_mut() {
+            buffer.clear();
+        }
+
+        *self = Chip8 {
+            memory: EmulatedMemory::with_size(self.memory.mem_array.len()),
+            pc: self.load_address,
+            load_address: self.load_address,
+            instructions_per_frame: self.instructions_per_frame,
+            platform: self.platform,
+            quirks: self.quirks,
+            rpl_flags: self.rpl_flags,
+            rewind,
+            debugger: self.debugger.after_reset(),
+            tracer: self.tracer.take(),
+            ..Self::default()
+        };
+    }
+
+    /// Executes one 60 Hz frame: instructions_per_frame() instructions followed by a single
+    /// timers tick. Hosts are expected to call it FRAME_RATE times per second
+    ///
+    /// With the display_wait quirk the frame ends early once a sprite is drawn. It also ends
+    /// early when the debugger stops the machine, and does nothing at all while paused
+    pub fn run_frame(&mut self) -> Result<(), EmulationError> {
+        if self.debugger.is_paused() {
+            return Ok(());
+        }
+
+        self.record_rewind_snapshot();
+        self.wait_for_vblank = false;
+
+        for _ in 0..self.instructions_per_frame {
+            if self.debug_before_cycle() {
+                break;
+            }
 
-        println!("Opcode is: {:#0x}", opcode);
+            if let Err(error) = self.emulate_cycle() {
+                self.debugger.stop(StopReason::Error(error));
+                return Err(error);
+            }
 
-        let state = self.execute_opcode(opcode);
+            if self.debug_after_cycle() || self.wait_for_vblank || self.halted {
+                break;
+            }
+        }
+
+        self.tick_timers();
+
+        Ok(())
+    }
+
+    /// Decrements the delay and sound timers once, as done at the end of every frame
+    pub fn tick_timers(&mut self) {
+        self.timers.tick();
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    /// Sets how many instructions run_frame() executes. Values below 1 are raised to 1
+    pub fn set_instructions_per_frame(&mut self, instructions: usize) {
+        self.instructions_per_frame = instructions.max(1);
+    }
+
+    /// Fetches and executes a single instruction. Timers are not affected, see run_frame()
+    ///
+    /// On error the machine state is left untouched, with pc() pointing to the faulty instruction
+    pub fn emulate_cycle(&mut self) -> Result<(), EmulationError> {
+        if self.halted {
+            return Ok(());
+        }
+
+        self.last_access = None;
+        let trace = self.trace_start();
+        let opcode = self.fetch_opcode()?;
+        let state = self.execute_opcode(opcode)?;
 
-        println!("Pc value is: {}", self.pc);
         self.pc = match state {
             ExecutionState::Hold => self.pc,
-            ExecutionState::Skip => self.pc + 4,
+            ExecutionState::Skip => self.pc + 2 + self.instruction_length(self.pc + 2),
             ExecutionState::Continue => self.pc + 2,
             ExecutionState::JumpTo(address) => address,
             ExecutionState::ReturnTo(address) => address + 2,
         };
 
-        self.timers.tick();
+        if let Some(start) = trace {
+            self.trace_end(start);
+        }
+        Ok(())
     }
 
     fn skip_if_equal<T: PartialEq>(&mut self, a: T, b: T) -> ExecutionState {
@@ -100,51 +317,348 @@ impl Chip8 {
         }
     }
 
-    fn draw(&mut self, vx: u8, vy: u8, n: u8) -> ExecutionState {
-        let sprite = &self.memory.mem_array[self.memory.index..self.memory.index + n as usize];
-        self.cpu.register[0xF] = self.graphics.draw_sprite(vx, vy, n, sprite) as u8;
+    fn draw(&mut self, vx: u8, vy: u8, n: u8) -> Result<ExecutionState, EmulationError> {
+        let len = n as usize * self.graphics.plane_count();
+        self.access_at_index(Access::Read, len)?;
+
+        let sprite = &self.memory.mem_array[self.memory.index..self.memory.index + len];
+        self.cpu.register[0xF] =
+            self.graphics
+                .draw_sprite(vx, vy, n, sprite, self.quirks.clip_sprites) as u8;
+
+        self.draw_flag = true;
+        self.wait_for_vblank = self.quirks.display_wait;
+
+        Ok(ExecutionState::Continue)
+    }
+
+    fn draw_large(&mut self, vx: u8, vy: u8) -> Result<ExecutionState, EmulationError> {
+        let len = 32 * self.graphics.plane_count();
+        self.access_at_index(Access::Read, len)?;
+
+        let sprite = &self.memory.mem_array[self.memory.index..self.memory.index + len];
+        self.cpu.register[0xF] =
+            self.graphics
+                .draw_large_sprite(vx, vy, sprite, self.quirks.clip_sprites) as u8;
 
         self.draw_flag = true;
+        self.wait_for_vblank = self.quirks.display_wait;
+
+        Ok(ExecutionState::Continue)
+    }
+
+    fn exit(&mut self) -> ExecutionState {
+        self.halted = true;
+
+        ExecutionState::Hold
+    }
+
+    fn store_rpl_flags(&mut self, x: u8) -> ExecutionState {
+        self.rpl_flags[..=x as usize].copy_from_slice(&self.cpu.register[..=x as usize]);
 
         ExecutionState::Continue
     }
 
-    fn set_index_sprite_location(&mut self, vx: u8) -> ExecutionState {
-        self.memory.index = vx as usize * 5;
+    fn read_rpl_flags(&mut self, x: u8) -> ExecutionState {
+        self.cpu.register[..=x as usize].copy_from_slice(&self.rpl_flags[..=x as usize]);
 
         ExecutionState::Continue
     }
 
-    fn read_registers(&mut self, x: u8) -> ExecutionState {
+    fn read_registers(&mut self, x: u8) -> Result<ExecutionState, EmulationError> {
+        self.access_at_index(Access::Read, x as usize + 1)?;
+
         for index in 0..=x as usize {
-            self.cpu.register[index] = self.memory.mem_array[self.memory.index as usize + index];
+            self.cpu.register[index] = self.memory.mem_array[self.memory.index + index];
         }
+        self.increment_index_after_load_store(x);
 
-        ExecutionState::Continue
+        Ok(ExecutionState::Continue)
     }
 
-    fn store_registers(&mut self, x: u8) -> ExecutionState {
+    fn store_registers(&mut self, x: u8) -> Result<ExecutionState, EmulationError> {
+        self.access_at_index(Access::Write, x as usize + 1)?;
+
         for index in 0..=x as usize {
-            self.memory.mem_array[self.memory.index as usize + index] = self.cpu.register[index];
+            self.memory.mem_array[self.memory.index + index] = self.cpu.register[index];
         }
+        self.increment_index_after_load_store(x);
 
-        ExecutionState::Continue
+        Ok(ExecutionState::Continue)
+    }
+
+    fn increment_index_after_load_store(&mut self, x: u8) {
+        self.memory.index += match self.quirks.load_store_index {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => x as usize,
+            IndexIncrement::ByXPlusOne => x as usize + 1,
+        };
+    }
+
+    /// Applies the logic_resets_vf quirk after 8XY1/8XY2/8XY3
+    fn logic_operation(&mut self, state: ExecutionState) -> ExecutionState {
+        if self.quirks.logic_resets_vf {
+            self.cpu.register[0xF] = 0;
+        }
+
+        state
+    }
+
+    /// BNNN jumps to NNN + V0, or to XNN + VX with the jump_uses_vx quirk
+    fn jump_with_offset(&mut self, x: u8, nnn: usize) -> ExecutionState {
+        let offset_register = if self.quirks.jump_uses_vx { x } else { 0x0 };
+
+        self.memory
+            .jump_to_address(nnn + self.cpu.register[offset_register as usize] as usize)
+    }
+
+    /// Source register for 8XY6/8XYE, depending on the shift_uses_vy quirk
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            y
+        } else {
+            x
+        }
+    }
+
+    /// Size in bytes of the instruction at address, 4 for the XO-CHIP F000 NNNN
+    fn instruction_length(&self, address: usize) -> usize {
+        let is_long_load = self.memory.mem_array.get(address..address + 2) == Some(&[0xF0, 0x00]);
+
+        if is_long_load && self.platform.supports_xochip() {
+            4
+        } else {
+            2
+        }
+    }
+
+    /// F000 NNNN: loads the 16 bits address following the instruction into I
+    fn load_long_index(&mut self) -> Result<ExecutionState, EmulationError> {
+        self.memory.check_bounds(self.pc + 2, 2)?;
+
+        let address = (self.memory.mem_array[self.pc + 2] as usize) << 8
+            | self.memory.mem_array[self.pc + 3] as usize;
+        self.memory.set_index(address);
+
+        Ok(ExecutionState::JumpTo(self.pc + 4))
+    }
+
+    /// Registers from x to y, in descending order if x > y
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        if x <= y {
+            (x as usize..=y as usize).collect()
+        } else {
+            (y as usize..=x as usize).rev().collect()
+        }
+    }
+
+    /// 5XY2: stores registers x to y starting at I, which is left untouched
+    fn store_register_range(&mut self, x: u8, y: u8) -> Result<ExecutionState, EmulationError> {
+        let registers = Self::register_range(x, y);
+        self.access_at_index(Access::Write, registers.len())?;
+
+        for (offset, register) in registers.into_iter().enumerate() {
+            self.memory.mem_array[self.memory.index + offset] = self.cpu.register[register];
+        }
+
+        Ok(ExecutionState::Continue)
+    }
+
+    /// 5XY3: reads registers x to y starting at I, which is left untouched
+    fn read_register_range(&mut self, x: u8, y: u8) -> Result<ExecutionState, EmulationError> {
+        let registers = Self::register_range(x, y);
+        self.access_at_index(Access::Read, registers.len())?;
+
+        for (offset, register) in registers.into_iter().enumerate() {
+            self.cpu.register[register] = self.memory.mem_array[self.memory.index + offset];
+        }
+
+        Ok(ExecutionState::Continue)
+    }
+
+    fn load_audio_pattern(&mut self) -> Result<ExecutionState, EmulationError> {
+        self.access_at_index(Access::Read, 16)?;
+
+        let index = self.memory.index;
+        Ok(self
+            .audio
+            .load_pattern(&self.memory.mem_array[index..index + 16]))
+    }
+
+    /// FX33: stores the decimal digits of VX at I
+    fn store_bcd(&mut self, vx: u8) -> Result<ExecutionState, EmulationError> {
+        self.access_at_index(Access::Write, 3)?;
+
+        self.memory.memory_store_bcd(vx)
+    }
+
+    /// Checks that the length bytes at I exist and reports them as accessed by the current
+    /// instruction, for memory watchpoints and the tracer
+    fn access_at_index(&mut self, access: Access, length: usize) -> Result<(), EmulationError> {
+        self.memory.check_bounds(self.memory.index, length)?;
+        self.last_access = Some((access, self.memory.index, length));
+
+        Ok(())
+    }
+
+    fn fetch_opcode(&mut self) -> Result<u16, EmulationError> {
+        self.memory.check_bounds(self.pc, 2)?;
+
+        Ok(
+            (self.memory.mem_array[self.pc] as u16) << 8
+                | self.memory.mem_array[self.pc + 1] as u16,
+        )
+    }
+
+    /// Returns EmulationError::InvalidKey if key >= 16
+    pub fn press_key(&mut self, key: u8) -> Result<(), EmulationError> {
+        self.keypad.press_key(key)
+    }
+
+    /// Returns EmulationError::InvalidKey if key >= 16
+    pub fn release_key(&mut self, key: u8) -> Result<(), EmulationError> {
+        self.keypad.release_key(key)
+    }
+
+    /// Returns the 16 general purpose registers V0..VF
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.cpu.register
+    }
+
+    /// Returns the value stored on register[x]
+    pub fn register(&self, x: u8) -> u8 {
+        self.cpu.register[x as usize]
+    }
+
+    /// Stores value on register[x]
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.cpu.set_register(x, value);
+    }
+
+    /// Address of the next instruction to be fetched
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Value of the index register (I)
+    pub fn index(&self) -> usize {
+        self.memory.index
+    }
+
+    pub fn set_index(&mut self, index: usize) {
+        self.memory.set_index(index);
+    }
+
+    /// Returns the whole call stack. Only the first stack_pointer() entries are in use
+    pub fn stack(&self) -> &[u16; 16] {
+        &self.memory.stack
+    }
+
+    pub fn stack_mut(&mut self) -> &mut [u16; 16] {
+        &mut self.memory.stack
+    }
+
+    pub fn stack_pointer(&self) -> usize {
+        self.memory.stack_pointer
+    }
+
+    pub fn set_stack_pointer(&mut self, stack_pointer: usize) {
+        self.memory.stack_pointer = stack_pointer;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.timers.get_delay_timer()
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.timers.set_delay_timer(value);
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.timers.get_sound_timer()
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.timers.set_sound_timer(value);
+    }
+
+    /// Whether the buzzer sounds, which it does as long as the sound timer is non-zero
+    ///
+    /// Frontends query it after every frame to start and stop their audio output. It stays
+    /// on during the frame where the timer reaches zero, so a value of N sounds N frames
+    pub fn is_sound_on(&self) -> bool {
+        self.timers.is_buzzing()
+    }
+
+    /// Returns the whole addressable memory, including the font set
+    pub fn memory(&self) -> &[u8] {
+        &self.memory.mem_array
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory.mem_array
+    }
+
+    /// Returns the state of the 16 keys. 1 means pressed, 0 released
+    pub fn keypad(&self) -> &[u8; 16] {
+        &self.keypad.keypad
+    }
+
+    /// Returns the display buffer, one byte per pixel, row-major. See display_size()
+    ///
+    /// Pixels hold one bit per XO-CHIP plane: 0 is off, 1 and 2 are set on a single plane
+    /// and 3 is set on both
+    pub fn display(&self) -> &[u8] {
+        self.graphics.pixels()
+    }
+
+    /// Current resolution as (width, height). 64x32, or 128x64 on SUPER-CHIP hires mode
+    pub fn display_size(&self) -> (usize, usize) {
+        self.graphics.size()
+    }
+
+    /// Set once the program executes the SUPER-CHIP exit instruction (00FD)
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// SUPER-CHIP user flags written by FX75 and read by FX85
+    ///
+    /// They are meant to outlive the running program, so frontends can store them
+    /// and give them back with set_rpl_flags()
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl_flags
     }
 
-    fn fetch_opcode(&mut self) -> u16 {
-        (self.memory.mem_array[self.pc as usize] as u16) << 8
-            | self.memory.mem_array[self.pc as usize + 1] as u16
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl_flags = flags;
     }
 
-    pub fn press_key(&mut self, key: u8) {
-        self.keypad.press_key(key);
+    /// XO-CHIP 1-bit audio pattern loaded by F002, or None if the program never loaded one
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio.pattern.as_ref()
     }
 
-    pub fn release_key(&mut self, key: u8) {
-        self.keypad.release_key(key);
+    /// XO-CHIP pitch register set by FX3A. See audio_playback_rate()
+    pub fn pitch(&self) -> u8 {
+        self.audio.pitch
     }
 
-    fn execute_opcode(&mut self, opcode: u16) -> ExecutionState {
+    /// Rate at which the audio pattern bits are played, in bits per second
+    pub fn audio_playback_rate(&self) -> f64 {
+        self.audio.playback_rate()
+    }
+
+    /// Set once the first sprite has been drawn, meaning display() has something worth rendering
+    pub fn draw_flag(&self) -> bool {
+        self.draw_flag
+    }
+
+    fn execute_opcode(&mut self, opcode: u16) -> Result<ExecutionState, EmulationError> {
         let f = ((opcode & 0xF000) >> 12) as u8;
         let x = ((opcode & 0x0F00) >> 8) as u8;
         let y = ((opcode & 0x00F0) >> 4) as u8;
@@ -155,45 +669,405 @@ impl Chip8 {
         let vx = self.cpu.register[x as usize];
         let vy = self.cpu.register[y as usize];
 
+        let schip = self.platform.supports_superchip();
+        let xochip = self.platform.supports_xochip();
+
         match (f, x, y, n) {
             (0x0, 0x0, 0xE, 0xE) => self.memory.return_from_subroutine(),
-            (0x0, _, 0xE, 0x0) => self.graphics.clear_display(),
-            (0x0, _, _, _) => ExecutionState::Continue,
-            (0x1, _, _, _) => self.memory.jump_to_address(nnn),
+            (0x0, _, 0xE, 0x0) => Ok(self.graphics.clear_display()),
+            (0x0, 0x0, 0xC, _) if schip => Ok(self.graphics.scroll_down(n)),
+            (0x0, 0x0, 0xD, _) if xochip => Ok(self.graphics.scroll_up(n)),
+            (0x0, 0x0, 0xF, 0xB) if schip => Ok(self.graphics.scroll_right()),
+            (0x0, 0x0, 0xF, 0xC) if schip => Ok(self.graphics.scroll_left()),
+            (0x0, 0x0, 0xF, 0xD) if schip => Ok(self.exit()),
+            (0x0, 0x0, 0xF, 0xE) if schip => Ok(self.graphics.set_hires(false)),
+            (0x0, 0x0, 0xF, 0xF) if schip => Ok(self.graphics.set_hires(true)),
+            (0x0, _, _, _) => Ok(ExecutionState::Continue),
+            (0x1, _, _, _) => Ok(self.memory.jump_to_address(nnn)),
             (0x2, _, _, _) => self.memory.call_subroutine(nnn, self.pc),
-            (0x3, _, _, _) => self.skip_if_equal(vx, kk),
-            (0x4, _, _, _) => self.skip_if_diff(vx, kk),
-            (0x5, _, _, _) => self.skip_if_equal(vx, vy),
-            (0x6, _, _, _) => self.cpu.set_register(x, kk),
-            (0x7, _, _, _) => self.cpu.register_add_value(x, kk),
-            (0x8, _, _, 0) => self.cpu.set_register(x, vy),
-            (0x8, _, _, 1) => self.cpu.register_or(x, y),
-            (0x8, _, _, 2) => self.cpu.register_and(x, y),
-            (0x8, _, _, 3) => self.cpu.register_xor(x, y),
-            (0x8, _, _, 4) => self.cpu.register_carry_add(x, y),
-            (0x8, _, _, 5) => self.cpu.register_borrow_sub(x, y),
-            (0x8, _, _, 6) => self.cpu.register_shr(x),
-            (0x8, _, _, 7) => self.cpu.register_borrow_sub_rev(x, y),
-            (0x8, _, _, 0xE) => self.cpu.register_shl(x),
-            (0x9, _, _, _) => self.skip_if_diff(vx, vy),
-            (0xA, _, _, _) => self.memory.set_index(nnn),
-            (0xB, _, _, _) => self
-                .memory
-                .jump_to_address(nnn + self.cpu.register[0x0] as usize),
-            (0xC, _, _, _) => self.cpu.register_random_and(x, kk),
+            (0x3, _, _, _) => Ok(self.skip_if_equal(vx, kk)),
+            (0x4, _, _, _) => Ok(self.skip_if_diff(vx, kk)),
+            (0x5, _, _, 0x2) if xochip => self.store_register_range(x, y),
+            (0x5, _, _, 0x3) if xochip => self.read_register_range(x, y),
+            (0x5, _, _, _) => Ok(self.skip_if_equal(vx, vy)),
+            (0x6, _, _, _) => Ok(self.cpu.set_register(x, kk)),
+            (0x7, _, _, _) => Ok(self.cpu.register_add_value(x, kk)),
+            (0x8, _, _, 0) => Ok(self.cpu.set_register(x, vy)),
+            (0x8, _, _, 1) => {
+                let state = self.cpu.register_or(x, y);
+                Ok(self.logic_operation(state))
+            }
+            (0x8, _, _, 2) => {
+                let state = self.cpu.register_and(x, y);
+                Ok(self.logic_operation(state))
+            }
+            (0x8, _, _, 3) => {
+                let state = self.cpu.register_xor(x, y);
+                Ok(self.logic_operation(state))
+            }
+            (0x8, _, _, 4) => Ok(self.cpu.register_carry_add(x, y)),
+            (0x8, _, _, 5) => Ok(self.cpu.register_borrow_sub(x, y)),
+            (0x8, _, _, 6) => Ok(self.cpu.register_shr(x, self.shift_source(x, y))),
+            (0x8, _, _, 7) => Ok(self.cpu.register_borrow_sub_rev(x, y)),
+            (0x8, _, _, 0xE) => Ok(self.cpu.register_shl(x, self.shift_source(x, y))),
+            (0x9, _, _, _) => Ok(self.skip_if_diff(vx, vy)),
+            (0xA, _, _, _) => Ok(self.memory.set_index(nnn)),
+            (0xB, _, _, _) => Ok(self.jump_with_offset(x, nnn)),
+            (0xC, _, _, _) => Ok(self.cpu.register_random_and(x, kk)),
+            (0xD, _, _, 0x0) if schip => self.draw_large(vx, vy),
             (0xD, _, _, _) => self.draw(vx, vy, n),
             (0xE, _, _, 0xE) => self.keypad.skip_if_pressed(vx),
             (0xE, _, _, 0x1) => self.keypad.skip_if_released(vx),
-            (0xF, _, _, 0x7) => self.cpu.set_register(x, self.timers.get_delay_timer()),
-            (0xF, _, _, 0xA) => self.keypad.wait_for_key(&mut self.cpu.register[x as usize]),
-            (0xF, _, 0x1, 0x5) => self.timers.set_delay_timer(vx),
-            (0xF, _, _, 0x8) => self.timers.set_sound_timer(vx),
-            (0xF, _, _, 0xE) => self.memory.index_add(x),
-            (0xF, _, _, 0x9) => self.set_index_sprite_location(vx),
-            (0xF, _, _, 0x3) => self.memory.memory_store_bcd(vx),
+            (0xF, 0x0, 0x0, 0x0) if xochip => self.load_long_index(),
+            (0xF, _, 0x0, 0x1) if xochip => Ok(self.graphics.select_planes(x)),
+            (0xF, 0x0, 0x0, 0x2) if xochip => self.load_audio_pattern(),
+            (0xF, _, 0x3, 0xA) if xochip => Ok(self.audio.set_pitch(vx)),
+            (0xF, _, _, 0x7) => Ok(self.cpu.set_register(x, self.timers.get_delay_timer())),
+            (0xF, _, _, 0xA) => Ok(self.keypad.wait_for_key(&mut self.cpu.register[x as usize])),
+            (0xF, _, 0x1, 0x5) => Ok(self.timers.set_delay_timer(vx)),
+            (0xF, _, _, 0x8) => Ok(self.timers.set_sound_timer(vx)),
+            (0xF, _, _, 0xE) => Ok(self.memory.index_add(vx)),
+            (0xF, _, _, 0x9) => Ok(self.memory.set_index_font(vx)),
+            (0xF, _, _, 0x3) => self.store_bcd(vx),
             (0xF, _, 0x5, 0x5) => self.store_registers(x),
             (0xF, _, 0x6, 0x5) => self.read_registers(x),
-            _ => panic!("Uknown opcode: {}", opcode),
+            (0xF, _, 0x3, 0x0) if schip => Ok(self.memory.set_index_big_font(vx)),
+            (0xF, _, 0x7, 0x5) if schip => Ok(self.store_rpl_flags(x)),
+            (0xF, _, 0x8, 0x5) if schip => Ok(self.read_rpl_flags(x)),
+            _ => Err(EmulationError::UnknownOpcode {
+                opcode,
+                address: self.pc,
+            }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chip8_initialization() {
+        let chip8 = Chip8::new();
+
+        assert_eq!(chip8.pc(), PROGRAM_START);
+        assert_eq!(chip8.index(), 0);
+        assert_eq!(chip8.stack_pointer(), 0);
+        assert_eq!(chip8.registers(), &[0; 16]);
+        assert_eq!(chip8.delay_timer(), 0);
+        assert_eq!(chip8.sound_timer(), 0);
+        assert!(!chip8.draw_flag());
+    }
+
+    #[test]
+    fn test_state_accessors() {
+        let mut chip8 = Chip8::new();
+
+        chip8.set_register(0x3, 0x42);
+        chip8.set_pc(0x300);
+        chip8.set_index(0x123);
+        chip8.set_delay_timer(10);
+        chip8.set_sound_timer(20);
+        chip8.stack_mut()[0] = 0x250;
+        chip8.set_stack_pointer(1);
+        chip8.memory_mut()[0x300] = 0xAB;
+        chip8.press_key(0xA).unwrap();
+
+        assert_eq!(chip8.register(0x3), 0x42);
+        assert_eq!(chip8.pc(), 0x300);
+        assert_eq!(chip8.index(), 0x123);
+        assert_eq!(chip8.delay_timer(), 10);
+        assert_eq!(chip8.sound_timer(), 20);
+        assert!(chip8.is_sound_on());
+        assert_eq!(chip8.stack()[..chip8.stack_pointer()], [0x250]);
+        assert_eq!(chip8.memory()[0x300], 0xAB);
+        assert_eq!(chip8.keypad()[0xA], 1);
+    }
+
+    #[test]
+    fn test_emulate_cycle() {
+        let mut chip8 = Chip8::new();
+
+        // 6A05: LD VA, 0x05 / A123: LD I, 0x123
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 4]
+            .copy_from_slice(&[0x6A, 0x05, 0xA1, 0x23]);
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+
+        assert_eq!(chip8.register(0xA), 0x05);
+        assert_eq!(chip8.index(), 0x123);
+        assert_eq!(chip8.pc(), PROGRAM_START + 4);
+    }
+
+    #[test]
+    fn test_add_to_index() {
+        let mut chip8 = Chip8::new();
+
+        // A300: LD I, 0x300 / 6205: LD V2, 0x05 / F21E: ADD I, V2
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 6]
+            .copy_from_slice(&[0xA3, 0x00, 0x62, 0x05, 0xF2, 0x1E]);
+        (0..3).for_each(|_| chip8.emulate_cycle().unwrap());
+
+        assert_eq!(chip8.index(), 0x305);
+    }
+
+    #[test]
+    fn test_emulation_errors() {
+        let mut chip8 = Chip8::new();
+
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 2].copy_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(
+            chip8.emulate_cycle(),
+            Err(EmulationError::UnknownOpcode {
+                opcode: 0xFFFF,
+                address: PROGRAM_START
+            })
+        );
+        assert_eq!(chip8.pc(), PROGRAM_START);
+
+        // 00EE: RET with an empty stack
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 2].copy_from_slice(&[0x00, 0xEE]);
+        assert_eq!(chip8.emulate_cycle(), Err(EmulationError::StackUnderflow));
+
+        // 2200: CALL 0x200, calling itself forever
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 2].copy_from_slice(&[0x22, 0x00]);
+        (0..16).for_each(|_| chip8.emulate_cycle().unwrap());
+        assert_eq!(chip8.emulate_cycle(), Err(EmulationError::StackOverflow));
+
+        // FF65: LD VF..V0, [I] past the end of memory
+        chip8.set_pc(PROGRAM_START);
+        chip8.set_index(0xFFA);
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 2].copy_from_slice(&[0xFF, 0x65]);
+        assert_eq!(
+            chip8.emulate_cycle(),
+            Err(EmulationError::MemoryOutOfBounds { address: 0x1000 })
+        );
+
+        // E09E: SKP V0 with V0 holding an invalid key
+        chip8.set_register(0x0, 0x20);
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 2].copy_from_slice(&[0xE0, 0x9E]);
+        assert_eq!(chip8.emulate_cycle(), Err(EmulationError::InvalidKey(0x20)));
+
+        chip8.set_pc(0xFFF);
+        assert_eq!(
+            chip8.emulate_cycle(),
+            Err(EmulationError::MemoryOutOfBounds { address: 0x1000 })
+        );
+
+        chip8.set_pc(usize::MAX);
+        assert_eq!(
+            chip8.emulate_cycle(),
+            Err(EmulationError::MemoryOutOfBounds {
+                address: usize::MAX
+            })
+        );
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut chip8 = Chip8::new();
+
+        // 7001: ADD V0, 0x01 / 1200: JP 0x200
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + 4]
+            .copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+        chip8.set_delay_timer(5);
+        chip8.set_instructions_per_frame(20);
+
+        chip8.set_sound_timer(1);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.register(0x0), 10);
+        assert_eq!(chip8.delay_timer(), 4);
+        assert_eq!(chip8.sound_timer(), 0);
+        assert!(chip8.is_sound_on());
+
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(chip8.delay_timer(), 4);
+        chip8.tick_timers();
+        assert!(!chip8.is_sound_on());
+
+        chip8.set_instructions_per_frame(0);
+        assert_eq!(chip8.instructions_per_frame(), 1);
+    }
+
+    #[test]
+    fn test_quirks() {
+        let program = [
+            0x63, 0x81, // LD V3, 0x81
+            0x82, 0x36, // SHR V2, V3
+            0x81, 0x21, // OR V1, V2
+            0xA3, 0x00, // LD I, 0x300
+            0xF1, 0x55, // LD [I], V1
+            0xB3, 0x00, // JP V0, 0x300
+        ];
+
+        let mut vip = Chip8::with_quirks(Quirks::cosmac_vip());
+        vip.memory_mut()[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(&program);
+        vip.set_instructions_per_frame(program.len() / 2);
+        vip.run_frame().unwrap();
+        assert_eq!(vip.register(0x2), 0x40);
+        assert_eq!(vip.register(0xF), 0);
+        assert_eq!(vip.index(), 0x302);
+        assert_eq!(vip.pc(), 0x300);
+
+        // Chip8::new() keeps the legacy quirks
+        let mut legacy = Chip8::new();
+        legacy.memory_mut()[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(&program);
+        legacy.set_instructions_per_frame(program.len() / 2);
+        legacy.run_frame().unwrap();
+        assert_eq!(legacy.register(0x2), 0x00);
+        assert_eq!(legacy.register(0xF), 0);
+        assert_eq!(legacy.index(), 0x300);
+        assert_eq!(legacy.pc(), 0x300);
+
+        let mut schip = Chip8::with_quirks(Quirks::superchip());
+        schip.memory_mut()[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(&program);
+        schip.set_instructions_per_frame(program.len() / 2);
+        schip.run_frame().unwrap();
+        assert_eq!(schip.register(0x2), 0x00);
+        assert_eq!(schip.register(0xF), 0);
+        assert_eq!(schip.index(), 0x300);
+        assert_eq!(schip.pc(), 0x381);
+    }
+
+    #[test]
+    fn test_display_wait() {
+        // D001: DRW V0, V0, 1 / 1200: JP 0x200
+        let program = [0xD0, 0x01, 0x12, 0x00];
+
+        let mut chip8 = Chip8::with_quirks(Quirks::cosmac_vip());
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(&program);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+
+        chip8.set_quirks(Quirks::chip48());
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.pc(), PROGRAM_START + 2);
+        assert!(chip8.draw_flag());
+    }
+
+    #[test]
+    fn test_superchip() {
+        let program = [
+            0x00, 0xFF, // HIGH
+            0x60, 0x07, // LD V0, 0x07
+            0xF0, 0x30, // LD HF, V0
+            0x61, 0x78, // LD V1, 0x78
+            0xD1, 0x10, // DRW V1, V1, 0
+            0x00, 0xC1, // SCD 1
+            0xF1, 0x75, // LD R, V1
+            0x00, 0xFD, // EXIT
+        ];
+
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip);
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(&program);
+        chip8.set_instructions_per_frame(20);
+        chip8.run_frame().unwrap();
+
+        assert!(chip8.is_halted());
+        assert_eq!(chip8.pc(), PROGRAM_START + program.len() - 2);
+        assert_eq!(chip8.display_size(), (128, 64));
+        assert_eq!(chip8.index(), 0xA0 + 7 * 10);
+        assert_eq!(chip8.rpl_flags()[..3], [0x07, 0x78, 0x00]);
+        // First line of the big 7 is 0xFF, drawn at (120, 120 % 64) then scrolled down by 1
+        let line = 128 * (120 % 64 + 1);
+        assert_eq!(chip8.display()[line + 120..line + 128], [1; 8]);
+
+        // Same program on a CHIP-8 platform stops at the unknown instruction
+        let mut chip8 = Chip8::new();
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(&program);
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        assert!(chip8.emulate_cycle().is_err());
+    }
+
+    #[test]
+    fn test_xochip() {
+        let program = [
+            0xF0, 0x00, 0x80, 0x00, // LD I, 0x8000
+            0x60, 0x01, // LD V0, 0x01
+            0x62, 0x03, // LD V2, 0x03
+            0x52, 0x02, // SAVE V2 - V0
+            0x30, 0x01, // SE V0, 0x01
+            0xF0, 0x00, 0x12, 0x00, // LD I, 0x1200 (skipped as a whole)
+            0xF3, 0x01, // PLANE 3
+            0x70, 0x10, // ADD V0, 0x10
+            0xF0, 0x3A, // PITCH V0
+            0xF0, 0x02, // AUDIO
+            0xD0, 0x01, // DRW V0, V0, 1
+        ];
+
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        assert_eq!(chip8.memory().len(), 0x10000);
+        chip8.memory_mut()[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(&program);
+        chip8.memory_mut()[0x8003..0x8005].copy_from_slice(&[0xFF, 0x80]);
+        chip8.set_instructions_per_frame(10);
+        chip8.run_frame().unwrap();
+
+        assert_eq!(chip8.index(), 0x8000);
+        assert_eq!(chip8.memory()[0x8000..0x8003], [0x03, 0x00, 0x01]);
+        assert_eq!(chip8.pitch(), 0x11);
+        assert_eq!(chip8.audio_pattern().unwrap()[3..5], [0xFF, 0x80]);
+        // Plane 1 gets the 0x03 line at I, plane 2 the 0x00 line at I + 1
+        assert_eq!(
+            chip8.display()[64 * 0x11 + 0x16..64 * 0x11 + 0x19],
+            [0, 1, 1]
+        );
+        assert_eq!(chip8.pc(), PROGRAM_START + program.len());
+    }
+
+    #[test]
+    fn test_load_program() {
+        let mut chip8 = Chip8::new();
+
+        assert!(matches!(
+            chip8.load_program_bytes(&[]),
+            Err(LoadError::Empty)
+        ));
+        assert!(matches!(
+            chip8.load_program_bytes(&[0; 3585]),
+            Err(LoadError::TooLarge {
+                size: 3585,
+                max_size: 3584,
+                address: PROGRAM_START
+            })
+        ));
+        assert!(matches!(
+            chip8.load_program("roms/missing.rom"),
+            Err(LoadError::Io(_))
+        ));
+
+        chip8.load_program("roms/pong.rom").unwrap();
+        assert_eq!(
+            chip8.memory()[PROGRAM_START..PROGRAM_START + 2],
+            [0x6A, 0x02]
+        );
+        chip8.load_program_bytes(&[0; 3584]).unwrap();
+    }
+
+    #[test]
+    fn test_reload_and_load_address() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip);
+        chip8.set_instructions_per_frame(3);
+        chip8.set_rpl_flags([1; 16]);
+
+        // 6005: LD V0, 0x05 / A300: LD I, 0x300 / 2300: CALL 0x300
+        chip8
+            .load_program_bytes(&[0x60, 0x05, 0xA3, 0x00, 0x23, 0x00])
+            .unwrap();
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.stack_pointer(), 1);
+
+        chip8.set_load_address(0x600);
+        chip8.load_program_bytes(&[0x00, 0xE0]).unwrap();
+        assert_eq!(chip8.pc(), 0x600);
+        assert_eq!(chip8.registers(), &[0; 16]);
+        assert_eq!(chip8.index(), 0);
+        assert_eq!(chip8.stack_pointer(), 0);
+        assert_eq!(chip8.memory()[PROGRAM_START..PROGRAM_START + 2], [0, 0]);
+        assert_eq!(chip8.memory()[0x600..0x602], [0x00, 0xE0]);
+        assert_eq!(chip8.platform(), Platform::SuperChip);
+        assert_eq!(chip8.instructions_per_frame(), 3);
+        assert_eq!(chip8.rpl_flags(), &[1; 16]);
+        assert_eq!(chip8.max_program_size(), 4096 - 0x600);
+    }
+}
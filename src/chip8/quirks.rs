@@ -0,0 +1,216 @@
+use super::emulated_memory::{EXTENDED_MEM_SIZE, MEM_SIZE};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How FX55/FX65 leave the index register after storing/reading registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// I is left untouched
+    Unchanged,
+    /// I ends up pointing to the last register accessed (I += X)
+    ByX,
+    /// I ends up pointing past the last register accessed (I += X + 1)
+    ByXPlusOne,
+}
+
+/// Behaviour of the instructions that differ between CHIP-8 interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift register[y] into register[x], instead of shifting register[x] in place
+    pub shift_uses_vy: bool,
+    /// Effect of FX55/FX65 on the index register
+    pub load_store_index: IndexIncrement,
+    /// BXNN jumps to XNN + register[x], instead of BNNN jumping to NNN + register[0]
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 set register[0xF] to 0
+    pub logic_resets_vf: bool,
+    /// Sprites are cut at the display edges, instead of wrapping around to the other side
+    pub clip_sprites: bool,
+    /// DXYN waits for the next frame, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// Behaviour of this emulator before the quirks were configurable, the default one:
+    /// shifts in place, I left untouched by FX55/FX65, BNNN, VF kept by the logic
+    /// operations, wrapping sprites and no display wait
+    pub fn legacy() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    /// Original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 interpreter for the HP-48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub fn superchip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::legacy()
+    }
+}
+
+/// CHIP-8 interpreters with a known quirks preset
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    #[default]
+    Legacy,
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 5] = [
+        Platform::Legacy,
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::SuperChip,
+        Platform::XoChip,
+    ];
+
+    /// Short name used to select the platform, e.g. from the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Legacy => "legacy",
+            Platform::CosmacVip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Platform::Legacy => "CHIP-8 with the quirks of the first versions of this emulator",
+            Platform::CosmacVip => "COSMAC VIP, the original CHIP-8 interpreter",
+            Platform::Chip48 => "CHIP-48 for the HP-48 calculators",
+            Platform::SuperChip => "SUPER-CHIP 1.1",
+            Platform::XoChip => "XO-CHIP, as implemented by Octo",
+        }
+    }
+
+    /// Whether the SUPER-CHIP instructions (hires mode, scrolling, big font...) are available
+    pub fn supports_superchip(self) -> bool {
+        matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+
+    /// Whether the XO-CHIP instructions (bitplanes, audio patterns, long index...) are available
+    pub fn supports_xochip(self) -> bool {
+        self == Platform::XoChip
+    }
+
+    /// Bytes of addressable memory, 4 KiB or 64 KiB for XO-CHIP
+    pub fn memory_size(self) -> usize {
+        if self.supports_xochip() {
+            EXTENDED_MEM_SIZE
+        } else {
+            MEM_SIZE
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Legacy => Quirks::legacy(),
+            Platform::CosmacVip => Quirks::cosmac_vip(),
+            Platform::Chip48 => Quirks::chip48(),
+            Platform::SuperChip => Quirks::superchip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Platform::ALL
+            .iter()
+            .copied()
+            .find(|platform| platform.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown platform '{}'", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_names() {
+        Platform::ALL.iter().for_each(|&platform| {
+            assert_eq!(platform.name().parse::<Platform>(), Ok(platform));
+        });
+
+        assert_eq!("SCHIP".parse::<Platform>(), Ok(Platform::SuperChip));
+        assert!("chip-9".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(Quirks::default(), Quirks::legacy());
+        assert_eq!(Platform::default().quirks(), Quirks::default());
+        assert_eq!(Platform::CosmacVip.quirks(), Quirks::cosmac_vip());
+        assert_eq!(Platform::XoChip.quirks(), Quirks::xochip());
+        assert!(!Quirks::xochip().clip_sprites);
+        assert_eq!(Quirks::chip48().load_store_index, IndexIncrement::ByX);
+    }
+}
@@ -0,0 +1,219 @@
+use super::{Chip8, Snapshot};
+
+use std::collections::VecDeque;
+
+/// Bytes compared at once between consecutive frames, which is also a row of the display
+const PAGE_SIZE: usize = 128;
+
+/// Ring buffer holding one snapshot per frame, the oldest ones being dropped first
+///
+/// Only the last frame is kept whole, as a save state. Every other frame is stored as
+/// the pages of the save state that differ from the next frame, which for most programs
+/// are the registers and a few memory pages and display rows
+pub struct RewindBuffer {
+    latest: Option<Vec<u8>>,
+    /// Changes taking each frame back to the one before it, the oldest frames first
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+/// Pages of a save state that differ from the save state of the next frame
+struct Delta {
+    size: usize,
+    pages: Vec<(usize, Box<[u8]>)>,
+}
+
+impl Delta {
+    fn between(state: &[u8], next: &[u8]) -> Self {
+        let pages = state
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .map(|(page, bytes)| (page * PAGE_SIZE, bytes))
+            .filter(|&(start, bytes)| next.get(start..start + bytes.len()) != Some(bytes))
+            .map(|(start, bytes)| (start, bytes.into()))
+            .collect();
+
+        Delta {
+            size: state.len(),
+            pages,
+        }
+    }
+
+    /// Turns the save state of the next frame back into the one of this frame
+    fn apply(&self, state: &mut Vec<u8>) {
+        state.resize(self.size, 0);
+        for (start, bytes) in &self.pages {
+            state[*start..][..bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            latest: None,
+            deltas: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, snapshot: &Snapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let state = snapshot.to_bytes();
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(Delta::between(&latest, &state));
+        }
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.latest = Some(state);
+    }
+
+    /// Drops the last frames snapshots, returning the oldest of them
+    ///
+    /// Goes back as far as possible if there are less than frames snapshots
+    pub fn pop(&mut self, frames: usize) -> Option<Snapshot> {
+        let frames = frames.min(self.len());
+        let mut state = self.latest.take().filter(|_| frames > 0)?;
+
+        for _ in 1..frames {
+            self.deltas.pop_back()?.apply(&mut state);
+        }
+        let snapshot = Snapshot::from_bytes(&state).ok();
+
+        if let Some(delta) = self.deltas.pop_back() {
+            delta.apply(&mut state);
+            self.latest = Some(state);
+        }
+        snapshot
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+impl Chip8 {
+    /// Starts recording a snapshot at the beginning of every run_frame(), keeping the
+    /// last capacity ones so the machine can be taken back with rewind()
+    pub fn enable_rewind(&mut self, capacity: usize) {
+        self.rewind = Some(RewindBuffer::new(capacity));
+    }
+
+    /// Stops recording snapshots, dropping the recorded ones
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// Number of frames rewind() can currently go back
+    pub fn rewind_frames(&self) -> usize {
+        self.rewind.as_ref().map_or(0, RewindBuffer::len)
+    }
+
+    /// Takes the machine back to the start of the frame run frames ago
+    ///
+    /// Returns how many frames were actually rewound, which is less than frames when the
+    /// history is shorter, and 0 when rewind is disabled
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let available = self.rewind_frames();
+
+        match self.rewind.as_mut().and_then(|buffer| buffer.pop(frames)) {
+            Some(snapshot) => {
+                self.restore(&snapshot);
+                available - self.rewind_frames()
+            }
+            None => 0,
+        }
+    }
+
+    /// Records the snapshot of the frame about to run, if rewind is enabled
+    pub(super) fn record_rewind_snapshot(&mut self) {
+        if self.rewind.is_some() {
+            let snapshot = self.snapshot();
+            if let Some(buffer) = self.rewind.as_mut() {
+                buffer.push(&snapshot);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Platform;
+
+    fn counting_machine() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        // 7001: ADD V0, 0x01 / 1200: JP 0x200
+        chip8.load_program_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip8.set_instructions_per_frame(2);
+
+        chip8
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut chip8 = counting_machine();
+        assert_eq!(chip8.rewind(1), 0);
+
+        chip8.enable_rewind(5);
+        (0..8).for_each(|_| chip8.run_frame().unwrap());
+        assert_eq!(chip8.register(0x0), 8);
+        assert_eq!(chip8.rewind_frames(), 5);
+
+        assert_eq!(chip8.rewind(1), 1);
+        assert_eq!(chip8.register(0x0), 7);
+
+        assert_eq!(chip8.rewind(2), 2);
+        assert_eq!(chip8.register(0x0), 5);
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.register(0x0), 6);
+        assert_eq!(chip8.rewind_frames(), 3);
+
+        assert_eq!(chip8.rewind(10), 3);
+        assert_eq!(chip8.register(0x0), 3);
+        assert_eq!(chip8.rewind(1), 0);
+    }
+
+    #[test]
+    fn test_compact_history() {
+        let mut chip8 = counting_machine();
+        chip8.set_platform(Platform::XoChip);
+        chip8.enable_rewind(10);
+        (0..10).for_each(|_| chip8.run_frame().unwrap());
+
+        // Only the page holding the registers changes from one frame to the next
+        let buffer = chip8.rewind.as_ref().unwrap();
+        assert_eq!(buffer.deltas.len(), 9);
+        assert!(buffer.deltas.iter().all(|delta| delta.pages.len() == 1));
+
+        assert_eq!(chip8.rewind(9), 9);
+        assert_eq!(chip8.register(0x0), 1);
+        assert_eq!(chip8.memory().len(), 0x10000);
+    }
+
+    #[test]
+    fn test_rewind_survives_reset() {
+        let mut chip8 = counting_machine();
+        chip8.enable_rewind(5);
+        chip8.run_frame().unwrap();
+
+        chip8.reset();
+        assert_eq!(chip8.rewind_frames(), 0);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.rewind_frames(), 1);
+
+        chip8.disable_rewind();
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.rewind_frames(), 0);
+    }
+}
@@ -0,0 +1,363 @@
+use super::emulated_audio::EmulatedAudio;
+use super::emulated_cpu::EmulatedCpu;
+use super::emulated_graphics::EmulatedGraphics;
+use super::emulated_memory::EmulatedMemory;
+use super::emulated_timers::EmulatedTimers;
+use super::{Chip8, IndexIncrement, Platform, Quirks, StateError};
+
+/// Signature at the start of every save state
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+
+/// Bumped whenever the save state layout changes
+const STATE_VERSION: u8 = 1;
+
+/// Copy of the whole machine, except for the keypad which belongs to the host
+///
+/// Obtained with Chip8::snapshot() and brought back with Chip8::restore()
+#[derive(Clone)]
+pub struct Snapshot {
+    memory: EmulatedMemory,
+    cpu: EmulatedCpu,
+    timers: EmulatedTimers,
+    graphics: EmulatedGraphics,
+    audio: EmulatedAudio,
+    draw_flag: bool,
+    pc: usize,
+    load_address: usize,
+    instructions_per_frame: usize,
+    platform: Platform,
+    quirks: Quirks,
+    wait_for_vblank: bool,
+    halted: bool,
+    rpl_flags: [u8; 16],
+}
+
+impl Chip8 {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.clone(),
+            cpu: self.cpu.clone(),
+            timers: self.timers,
+            graphics: self.graphics.clone(),
+            audio: self.audio,
+            draw_flag: self.draw_flag,
+            pc: self.pc,
+            load_address: self.load_address,
+            instructions_per_frame: self.instructions_per_frame,
+            platform: self.platform,
+            quirks: self.quirks,
+            wait_for_vblank: self.wait_for_vblank,
+            halted: self.halted,
+            rpl_flags: self.rpl_flags,
+        }
+    }
+
+    /// Puts the machine back in the state it had when snapshot was taken
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let snapshot = snapshot.clone();
+
+        self.memory = snapshot.memory;
+        self.cpu = snapshot.cpu;
+        self.timers = snapshot.timers;
+        self.graphics = snapshot.graphics;
+        self.audio = snapshot.audio;
+        self.draw_flag = snapshot.draw_flag;
+        self.pc = snapshot.pc;
+        self.load_address = snapshot.load_address;
+        self.instructions_per_frame = snapshot.instructions_per_frame;
+        self.platform = snapshot.platform;
+        self.quirks = snapshot.quirks;
+        self.wait_for_vblank = snapshot.wait_for_vblank;
+        self.halted = snapshot.halted;
+        self.rpl_flags = snapshot.rpl_flags;
+    }
+
+    /// Serializes the machine into a versioned binary save state
+    pub fn save_state(&self) -> Vec<u8> {
+        self.snapshot().to_bytes()
+    }
+
+    /// Restores a save state produced by save_state()
+    ///
+    /// On error the machine is left untouched
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let snapshot = Snapshot::from_bytes(data)?;
+        self.restore(&snapshot);
+
+        Ok(())
+    }
+}
+
+impl Snapshot {
+    /// Binary layout, all numbers little endian:
+    ///
+    /// magic, version, platform, quirks (6 bytes), instructions per frame (u32),
+    /// load address (u32), pc (u32), index (u32), stack pointer, stack (16 x u16),
+    /// registers (16), delay timer, sound timer, flags, planes, pitch, pattern flag,
+    /// pattern (16), RPL flags (16), memory size (u32), memory, display size (u32), display
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data =
+            Vec::with_capacity(self.memory.mem_array.len() + self.graphics.display.len() + 128);
+
+        data.extend_from_slice(STATE_MAGIC);
+        data.push(STATE_VERSION);
+
+        data.push(
+            Platform::ALL
+                .iter()
+                .position(|&platform| platform == self.platform)
+                .unwrap() as u8,
+        );
+        data.push(self.quirks.shift_uses_vy as u8);
+        data.push(match self.quirks.load_store_index {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => 1,
+            IndexIncrement::ByXPlusOne => 2,
+        });
+        data.push(self.quirks.jump_uses_vx as u8);
+        data.push(self.quirks.logic_resets_vf as u8);
+        data.push(self.quirks.clip_sprites as u8);
+        data.push(self.quirks.display_wait as u8);
+        data.extend_from_slice(&(self.instructions_per_frame as u32).to_le_bytes());
+        data.extend_from_slice(&(self.load_address as u32).to_le_bytes());
+
+        data.extend_from_slice(&(self.pc as u32).to_le_bytes());
+        data.extend_from_slice(&(self.memory.index as u32).to_le_bytes());
+        data.push(self.memory.stack_pointer as u8);
+        self.memory
+            .stack
+            .iter()
+            .for_each(|address| data.extend_from_slice(&address.to_le_bytes()));
+        data.extend_from_slice(&self.cpu.register);
+        data.push(self.timers.get_delay_timer());
+        data.push(self.timers.get_sound_timer());
+
+        let flags = self.draw_flag as u8
+            | (self.halted as u8) << 1
+            | (self.wait_for_vblank as u8) << 2
+            | (self.graphics.hires as u8) << 3;
+        data.push(flags);
+        data.push(self.graphics.planes);
+        data.push(self.audio.pitch);
+        data.push(self.audio.pattern.is_some() as u8);
+        data.extend_from_slice(&self.audio.pattern.unwrap_or([0; 16]));
+        data.extend_from_slice(&self.rpl_flags);
+
+        data.extend_from_slice(&(self.memory.mem_array.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.memory.mem_array);
+        data.extend_from_slice(&(self.graphics.display.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.graphics.display);
+
+        data
+    }
+
+    /// Parses the layout written by to_bytes()
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        let mut reader = StateReader { data };
+
+        if reader.bytes(4).ok() != Some(&STATE_MAGIC[..]) {
+            return Err(StateError::NotAState);
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let platform = *Platform::ALL
+            .get(reader.u8()? as usize)
+            .ok_or(StateError::InvalidField("platform"))?;
+        let quirks = Quirks {
+            shift_uses_vy: reader.bool()?,
+            load_store_index: match reader.u8()? {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::ByX,
+                2 => IndexIncrement::ByXPlusOne,
+                _ => return Err(StateError::InvalidField("quirks")),
+            },
+            jump_uses_vx: reader.bool()?,
+            logic_resets_vf: reader.bool()?,
+            clip_sprites: reader.bool()?,
+            display_wait: reader.bool()?,
+        };
+        let instructions_per_frame = reader.u32()? as usize;
+        let load_address = reader.u32()? as usize;
+
+        let pc = reader.u32()? as usize;
+        let index = reader.u32()? as usize;
+        let stack_pointer = reader.u8()? as usize;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        if stack_pointer > stack.len() {
+            return Err(StateError::InvalidField("stack pointer"));
+        }
+        let mut cpu = EmulatedCpu::new();
+        cpu.register.copy_from_slice(reader.bytes(16)?);
+        let mut timers = EmulatedTimers::new();
+        timers.set_delay_timer(reader.u8()?);
+        timers.set_sound_timer(reader.u8()?);
+
+        let flags = reader.u8()?;
+        let planes = reader.u8()?;
+        let pitch = reader.u8()?;
+        let has_pattern = reader.bool()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(reader.bytes(16)?);
+        let mut rpl_flags = [0; 16];
+        rpl_flags.copy_from_slice(reader.bytes(16)?);
+
+        let memory_size = reader.u32()? as usize;
+        if memory_size != platform.memory_size() {
+            return Err(StateError::InvalidField("memory size"));
+        }
+        let mut memory = EmulatedMemory::with_size(memory_size);
+        memory.mem_array.copy_from_slice(reader.bytes(memory_size)?);
+        memory.index = index;
+        memory.stack = stack;
+        memory.stack_pointer = stack_pointer;
+
+        let mut graphics = EmulatedGraphics::new();
+        let display_size = graphics.display.len();
+        if reader.u32()? as usize != display_size {
+            return Err(StateError::InvalidField("display size"));
+        }
+        graphics
+            .display
+            .copy_from_slice(reader.bytes(display_size)?);
+        if graphics.display.iter().any(|&pixel| pixel > 3) {
+            return Err(StateError::InvalidField("display"));
+        }
+        graphics.hires = flags & 0b1000 != 0;
+        graphics.planes = planes & 0b11;
+
+        Ok(Snapshot {
+            memory,
+            cpu,
+            timers,
+            graphics,
+            audio: EmulatedAudio {
+                pattern: if has_pattern { Some(pattern) } else { None },
+                pitch,
+            },
+            draw_flag: flags & 0b1 != 0,
+            pc,
+            load_address,
+            instructions_per_frame: instructions_per_frame.max(1),
+            platform,
+            quirks,
+            wait_for_vblank: flags & 0b100 != 0,
+            halted: flags & 0b10 != 0,
+            rpl_flags,
+        })
+    }
+}
+
+/// Consumes a save state from the front
+struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::PROGRAM_START;
+    use super::*;
+
+    fn running_machine() -> Chip8 {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        // 6A05: LD VA, 0x05 / FA15: LD DT, VA / FA29: LD F, VA / DAA5: DRW VA, VA, 5
+        // 2200: CALL 0x200
+        chip8
+            .load_program_bytes(&[0x6A, 0x05, 0xFA, 0x15, 0xFA, 0x29, 0xDA, 0xA5, 0x22, 0x00])
+            .unwrap();
+        chip8.set_instructions_per_frame(5);
+        chip8.set_rpl_flags([7; 16]);
+        chip8.run_frame().unwrap();
+
+        chip8
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut chip8 = running_machine();
+        let state = chip8.save_state();
+
+        chip8.run_frame().unwrap();
+        assert_ne!(chip8.save_state(), state);
+
+        let mut other = Chip8::new();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+        assert_eq!(other.platform(), Platform::XoChip);
+        assert_eq!(other.pc(), PROGRAM_START);
+        assert_eq!(other.stack_pointer(), 1);
+        assert_eq!(other.delay_timer(), 4);
+        assert_eq!(other.register(0xA), 5);
+        assert_eq!(other.rpl_flags(), &[7; 16]);
+        assert_eq!(other.display()[64 * 5 + 5..64 * 5 + 9], [1, 1, 1, 1]);
+
+        chip8.load_state(&state).unwrap();
+        chip8.run_frame().unwrap();
+        other.run_frame().unwrap();
+        assert_eq!(chip8.save_state(), other.save_state());
+    }
+
+    #[test]
+    fn test_invalid_states() {
+        let mut chip8 = running_machine();
+        let mut state = chip8.save_state();
+        let original = chip8.save_state();
+
+        assert_eq!(chip8.load_state(b"C8"), Err(StateError::NotAState));
+        assert_eq!(chip8.load_state(b"ROM!...."), Err(StateError::NotAState));
+        assert_eq!(
+            chip8.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+
+        state[4] = STATE_VERSION + 1;
+        assert_eq!(
+            chip8.load_state(&state),
+            Err(StateError::UnsupportedVersion(STATE_VERSION + 1))
+        );
+
+        state[4] = STATE_VERSION;
+        state[5] = 0xFF;
+        assert_eq!(
+            chip8.load_state(&state),
+            Err(StateError::InvalidField("platform"))
+        );
+
+        assert_eq!(chip8.save_state(), original);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::number::parse_number;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Names of program addresses, used by the debugger and disassembly to show labels
+///
+/// An address can have several names, which all resolve to it. The first one given is
+/// the one shown for the address, until it is moved to another address: the remaining
+/// name that sorts first is shown then
+///
+/// Symbol files have one 'ADDRESS NAME' line per label, like the ones written by
+/// chip8-asm. 'NAME ADDRESS' lines work too, and blank lines as well as lines starting
+/// with ';' or '#' are ignored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    /// Name shown for each address
+    names: BTreeMap<usize, String>,
+    addresses: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Reads a symbol file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("can't read {}: {}", path.display(), error))?;
+
+        text.parse()
+            .map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Names address, moving name if it was given to another address
+    pub fn insert(&mut self, name: &str, address: usize) {
+        let previous = self.addresses.insert(name.to_string(), address);
+        if let Some(previous) = previous.filter(|&previous| previous != address) {
+            if self.names.get(&previous).map(String::as_str) == Some(name) {
+                // Shows another name of the previous address, if it has one
+                match self
+                    .addresses
+                    .iter()
+                    .filter(|(_, &other)| other == previous)
+                    .min()
+                {
+                    Some((other, _)) => self.names.insert(previous, other.clone()),
+                    None => self.names.remove(&previous),
+                };
+            }
+        }
+        self.names
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Number of names
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn address(&self, name: &str) -> Option<usize> {
+        self.addresses.get(name).copied()
+    }
+
+    pub fn name(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// Name shown for each address
+    pub fn names(&self) -> &BTreeMap<usize, String> {
+        &self.names
+    }
+
+    /// Address of a symbol, or a number as read by parse_number()
+    pub fn resolve(&self, text: &str) -> Result<usize, String> {
+        if let Some(address) = self.address(text) {
+            return Ok(address);
+        }
+
+        parse_number(text).map_err(|error| {
+            if text.starts_with(|c: char| c.is_ascii_digit()) {
+                error
+            } else {
+                format!("unknown symbol '{}'", text)
+            }
+        })
+    }
+
+    /// Address followed by the closest symbol at or before it, e.g. 0x0206 <loop+2>
+    pub fn describe(&self, address: usize) -> String {
+        match self.names.range(..=address).next_back() {
+            Some((&start, name)) if start == address => format!("{:#06x} <{}>", address, name),
+            Some((&start, name)) => format!("{:#06x} <{}+{}>", address, name, address - start),
+            None => format!("{:#06x}", address),
+        }
+    }
+}
+
+impl FromStr for SymbolTable {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut symbols = SymbolTable::new();
+        let parse_address = |text: &str| parse_number(text).ok();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let symbol = match fields[..] {
+                [first, second] => match (parse_address(first), parse_address(second)) {
+                    (Some(address), None) => Some((second, address)),
+                    (None, Some(address)) => Some((first, address)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let (name, address) = symbol.ok_or_else(|| {
+                format!(
+                    "line {}: expected 'ADDRESS NAME', got '{}'",
+                    index + 1,
+                    line
+                )
+            })?;
+            symbols.insert(name, address);
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_file() {
+        let symbols: SymbolTable = "; written by hand\n0x0200 main\n\ndraw 0x20a\n0x0300 sprites"
+            .parse()
+            .unwrap();
+
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.address("draw"), Some(0x20A));
+        assert_eq!(symbols.name(0x300), Some("sprites"));
+        assert_eq!(symbols.resolve("main"), Ok(0x200));
+        assert_eq!(symbols.resolve("0x210"), Ok(0x210));
+        assert_eq!(
+            symbols.resolve("nowhere"),
+            Err("unknown symbol 'nowhere'".to_string())
+        );
+        assert_eq!(symbols.describe(0x20A), "0x020a <draw>");
+        assert_eq!(symbols.describe(0x20E), "0x020e <draw+4>");
+        assert_eq!(symbols.describe(0x100), "0x0100");
+
+        assert_eq!(
+            "0x200 main extra".parse::<SymbolTable>(),
+            Err("line 1: expected 'ADDRESS NAME', got '0x200 main extra'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("start", 0x200);
+        symbols.insert("main", 0x200);
+        symbols.insert("entry", 0x200);
+
+        assert_eq!(symbols.address("start"), Some(0x200));
+        assert_eq!(symbols.address("main"), Some(0x200));
+        assert_eq!(symbols.name(0x200), Some("start"));
+        assert_eq!(symbols.len(), 3);
+
+        symbols.insert("start", 0x204);
+        assert_eq!(symbols.name(0x200), Some("entry"));
+        assert_eq!(symbols.name(0x204), Some("start"));
+
+        symbols.insert("main", 0x204);
+        symbols.insert("entry", 0x204);
+        assert_eq!(symbols.name(0x200), None);
+        assert_eq!(symbols.name(0x204), Some("start"));
+        assert_eq!(symbols.len(), 3);
+    }
+}
@@ -0,0 +1,446 @@
+use super::{Access, Chip8};
+use crate::disassembler::{Disassembler, Syntax};
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Signature at the start of every binary trace
+const TRACE_MAGIC: &[u8; 4] = b"C8TR";
+
+/// Bumped whenever the binary trace layout changes
+const TRACE_VERSION: u8 = 1;
+
+/// How a Tracer writes its entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// One line per instruction, meant to be diffed
+    #[default]
+    Text,
+    /// Compact little endian records, read back with read_binary_trace()
+    Binary,
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceFormat::Text => write!(f, "text"),
+            TraceFormat::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!(
+                "unknown trace format '{}', expected text or binary",
+                name
+            )),
+        }
+    }
+}
+
+/// Register an instruction can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TracedRegister {
+    V(u8),
+    I,
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl TracedRegister {
+    fn id(self) -> u8 {
+        match self {
+            TracedRegister::V(x) => x,
+            TracedRegister::I => 16,
+            TracedRegister::StackPointer => 17,
+            TracedRegister::DelayTimer => 18,
+            TracedRegister::SoundTimer => 19,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0..=15 => Some(TracedRegister::V(id)),
+            16 => Some(TracedRegister::I),
+            17 => Some(TracedRegister::StackPointer),
+            18 => Some(TracedRegister::DelayTimer),
+            19 => Some(TracedRegister::SoundTimer),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TracedRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TracedRegister::V(x) => write!(f, "V{:X}", x),
+            TracedRegister::I => write!(f, "I"),
+            TracedRegister::StackPointer => write!(f, "SP"),
+            TracedRegister::DelayTimer => write!(f, "DT"),
+            TracedRegister::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+/// One executed instruction and its effects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Instructions executed since the tracer was attached, this one excluded
+    pub cycle: u64,
+    pub pc: usize,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    /// New value of each register the instruction changed
+    pub changes: Vec<(TracedRegister, u16)>,
+    /// Address and new value of each byte the instruction stored
+    pub writes: Vec<(usize, u8)>,
+}
+
+impl fmt::Display for TraceEntry {
+    /// e.g. '      12 0x0206 7001      ADD V0, #01              V0=02 VF=00'
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: String = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut line = format!(
+            "{:>8} {:#06x} {:<8}  {:<24}",
+            self.cycle, self.pc, bytes, self.mnemonic
+        );
+
+        for (register, value) in &self.changes {
+            match register {
+                TracedRegister::I => line.push_str(&format!(" I={:04x}", value)),
+                _ => line.push_str(&format!(" {}={:02x}", register, value)),
+            }
+        }
+        for (address, value) in &self.writes {
+            line.push_str(&format!(" [{:04x}]={:02x}", address, value));
+        }
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+impl TraceEntry {
+    /// Layout: cycle (u64), pc (u16), length, bytes, change count, changes as register id
+    /// (V0-VF are 0-15, then I, SP, DT and ST) and value (u16), write count (u16), writes
+    /// as address (u16) and value
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(16 + 3 * self.changes.len() + 3 * self.writes.len());
+
+        data.extend_from_slice(&self.cycle.to_le_bytes());
+        data.extend_from_slice(&(self.pc as u16).to_le_bytes());
+        data.push(self.bytes.len() as u8);
+        data.extend_from_slice(&self.bytes);
+        data.push(self.changes.len() as u8);
+        for (register, value) in &self.changes {
+            data.push(register.id());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&(self.writes.len() as u16).to_le_bytes());
+        for (address, value) in &self.writes {
+            data.extend_from_slice(&(*address as u16).to_le_bytes());
+            data.push(*value);
+        }
+        data
+    }
+}
+
+/// Reads back a trace written in TraceFormat::Binary
+///
+/// Mnemonics aren't stored, they are decoded again for the XO-CHIP instruction set
+pub fn read_binary_trace<R: Read>(mut reader: R) -> io::Result<Vec<TraceEntry>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let header = read_bytes(&mut reader, 5)?;
+    if &header[..4] != TRACE_MAGIC {
+        return Err(invalid("not a binary trace"));
+    }
+    if header[4] != TRACE_VERSION {
+        return Err(invalid("unsupported trace version"));
+    }
+
+    let disassembler = Disassembler::default();
+    let mut entries = Vec::new();
+    loop {
+        let mut cycle = [0; 8];
+        match reader.read_exact(&mut cycle) {
+            Ok(()) => (),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error),
+        }
+        let pc = read_u16(&mut reader)? as usize;
+        let length = read_bytes(&mut reader, 1)?[0] as usize;
+        let bytes = read_bytes(&mut reader, length)?;
+
+        let mut changes = Vec::new();
+        for _ in 0..read_bytes(&mut reader, 1)?[0] {
+            let register = TracedRegister::from_id(read_bytes(&mut reader, 1)?[0])
+                .ok_or_else(|| invalid("unknown register in trace entry"))?;
+            changes.push((register, read_u16(&mut reader)?));
+        }
+        let mut writes = Vec::new();
+        for _ in 0..read_u16(&mut reader)? {
+            let address = read_u16(&mut reader)? as usize;
+            writes.push((address, read_bytes(&mut reader, 1)?[0]));
+        }
+
+        let mnemonic = if bytes.is_empty() {
+            String::new()
+        } else {
+            disassembler.decode(&bytes, pc).text
+        };
+        entries.push(TraceEntry {
+            cycle: u64::from_le_bytes(cycle),
+            pc,
+            bytes,
+            mnemonic,
+            changes,
+            writes,
+        });
+    }
+    Ok(entries)
+}
+
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let bytes = read_bytes(reader, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// Records the instructions a Chip8 executes, see Chip8::set_tracer()
+pub struct Tracer {
+    output: Box<dyn Write>,
+    format: TraceFormat,
+    /// Inclusive address ranges to trace, everything when empty
+    ranges: Vec<(usize, usize)>,
+    cycle: u64,
+    /// First write error, reported by finish()
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(output: W, format: TraceFormat) -> Self {
+        let mut tracer = Tracer {
+            output: Box::new(output),
+            format,
+            ranges: Vec::new(),
+            cycle: 0,
+            error: None,
+        };
+        if format == TraceFormat::Binary {
+            let mut header = TRACE_MAGIC.to_vec();
+            header.push(TRACE_VERSION);
+            tracer.write(&header);
+        }
+        tracer
+    }
+
+    /// Tracer writing to a new file at path
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(BufWriter::new(file), format))
+    }
+
+    /// Only traces instructions between start and end, both included. Can be called
+    /// several times to trace several ranges
+    pub fn add_range(&mut self, start: usize, end: usize) {
+        self.ranges.push((start, end));
+    }
+
+    pub fn traces(&self, address: usize) -> bool {
+        self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| address >= start && address <= end)
+    }
+
+    /// Instructions executed since the tracer was attached, traced or not
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Flushes the output, returning the first error met while tracing
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.output.flush()
+    }
+
+    fn record(&mut self, entry: &TraceEntry) {
+        match self.format {
+            TraceFormat::Text => self.write(format!("{}\n", entry).as_bytes()),
+            TraceFormat::Binary => self.write(&entry.to_bytes()),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if self.error.is_none() {
+            if let Err(error) = self.output.write_all(data) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+/// Machine state before a traced instruction
+pub(super) struct TraceStart {
+    cycle: u64,
+    pc: usize,
+    bytes: Vec<u8>,
+    values: [u16; 20],
+}
+
+impl Chip8 {
+    /// Starts recording every instruction executed, replacing the current tracer
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stops tracing, call Tracer::finish() on the result to flush it
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Values of the TracedRegisters, indexed by their id
+    fn traced_values(&self) -> [u16; 20] {
+        let mut values = [0; 20];
+        for (value, &register) in values.iter_mut().zip(self.cpu.register.iter()) {
+            *value = register as u16;
+        }
+        values[16] = self.memory.index as u16;
+        values[17] = self.stack_pointer() as u16;
+        values[18] = self.delay_timer() as u16;
+        values[19] = self.sound_timer() as u16;
+        values
+    }
+
+    /// Called before executing the instruction at pc, None when it isn't traced
+    pub(super) fn trace_start(&mut self) -> Option<TraceStart> {
+        let tracer = self.tracer.as_mut()?;
+        let cycle = tracer.cycle;
+        tracer.cycle += 1;
+        if !tracer.traces(self.pc) {
+            return None;
+        }
+
+        let end = (self.pc + self.instruction_length(self.pc)).min(self.memory.mem_array.len());
+        Some(TraceStart {
+            cycle,
+            pc: self.pc,
+            bytes: self.memory.mem_array[self.pc.min(end)..end].to_vec(),
+            values: self.traced_values(),
+        })
+    }
+
+    /// Called once the instruction started with trace_start() is executed
+    pub(super) fn trace_end(&mut self, start: TraceStart) {
+        let values = self.traced_values();
+        let changes = (0..20)
+            .filter(|&id| values[id] != start.values[id])
+            .map(|id| (TracedRegister::from_id(id as u8).unwrap(), values[id]))
+            .collect();
+
+        let writes = match self.last_access {
+            Some((Access::Write, address, length)) => (address..address + length)
+                .map(|address| (address, self.memory.mem_array[address]))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mnemonic = if start.bytes.is_empty() {
+            String::new()
+        } else {
+            let disassembler = Disassembler::new(Syntax::Conventional, self.platform);
+            let labels = self.debugger.symbols().names();
+            disassembler
+                .decode_with_labels(&start.bytes, start.pc, labels)
+                .text
+        };
+
+        let entry = TraceEntry {
+            cycle: start.cycle,
+            pc: start.pc,
+            bytes: start.bytes,
+            mnemonic,
+            changes,
+            writes,
+        };
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_trace() {
+        // 0x200: LD V0, 5 / 0x202: LD I, 0x300 / 0x204: LD B, V0 / 0x206: CALL 0x20A
+        // 0x208: JP 0x208 / 0x20A: RET
+        let program = [
+            0x60, 0x05, 0xA3, 0x00, 0xF0, 0x33, 0x22, 0x0A, 0x12, 0x08, 0x00, 0xEE,
+        ];
+        let path = std::env::temp_dir().join("chip8_test_trace.txt");
+        let binary_path = std::env::temp_dir().join("chip8_test_trace.bin");
+
+        let mut chip8 = Chip8::new();
+        chip8.load_program_bytes(&program).unwrap();
+        let mut tracer = Tracer::create(&path, TraceFormat::Text).unwrap();
+        tracer.add_range(0x200, 0x207);
+        chip8.set_tracer(tracer);
+        for _ in 0..5 {
+            chip8.emulate_cycle().unwrap();
+        }
+        chip8.take_tracer().unwrap().finish().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "       0 0x0200 6005      LD V0, #05               V0=05
+       1 0x0202 a300      LD I, #300               I=0300
+       2 0x0204 f033      LD B, V0                 [0300]=00 [0301]=00 [0302]=05
+       3 0x0206 220a      CALL #20A                SP=01
+"
+        );
+
+        chip8.reset();
+        chip8.load_program_bytes(&program).unwrap();
+        chip8.set_tracer(Tracer::create(&binary_path, TraceFormat::Binary).unwrap());
+        for _ in 0..3 {
+            chip8.emulate_cycle().unwrap();
+        }
+        chip8.take_tracer().unwrap().finish().unwrap();
+
+        let entries = read_binary_trace(File::open(&binary_path).unwrap()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].writes, [(0x300, 0), (0x301, 0), (0x302, 5)]);
+        assert_eq!(
+            entries[1].to_string(),
+            "       1 0x0202 a300      LD I, #300               I=0300"
+        );
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(binary_path).unwrap();
+    }
+}
@@ -0,0 +1,448 @@
+use crate::chip8::{Access, Chip8, Comparison, StopReason, SymbolTable, Watchpoint};
+use crate::disassembler::{self, Disassembler, Syntax};
+use crate::number::parse_number;
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+pub const HELP: &str = "\
+Debugger commands (numbers are decimal, or hexadecimal prefixed by 0x, and
+addresses can also be labels of the loaded symbol file):
+  c, continue         resume execution
+  p, pause            pause execution
+  s, step [N]         execute N instructions (default 1)
+  n, next             step over subroutine calls
+  o, finish           run until the current subroutine returns
+  u, until ADDR       run until pc reaches ADDR
+  b, break ADDR       add a breakpoint at ADDR
+  d, delete [ADDR]    remove the breakpoint at ADDR, or all of them
+  bl, breakpoints     list breakpoints
+  w, watch WATCH      add a watchpoint, WATCH being one of:
+      r|w|rw ADDR [END]   memory between ADDR and END is read, written or both
+      VX                  register VX changes
+      VX OP VALUE         register VX starts to meet OP (==, !=, <, <=, >, >=)
+      i ADDR [END]        I starts pointing between ADDR and END
+  wl, watchpoints     list watchpoints
+  uw, unwatch [N]     remove watchpoint N, or all of them
+  r, regs             show registers and timers
+  x, mem ADDR [LEN]   dump LEN bytes of memory from ADDR (default 64)
+  bt, stack           show the subroutine call stack
+  set REG VALUE       set V0-VF, pc, i, dt or st
+  h, help             show this help
+An empty line repeats the last command.";
+
+const DEFAULT_DUMP_LENGTH: usize = 64;
+
+/// Text command interpreter driving the debugger of a Chip8
+///
+/// Frontends feed it lines read from the user and print what it returns
+#[derive(Default)]
+pub struct DebugConsole {
+    last_command: Option<String>,
+}
+
+impl DebugConsole {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Runs a single command line, returning its output
+    pub fn execute(&mut self, chip8: &mut Chip8, line: &str) -> String {
+        let line = match line.trim() {
+            "" => match self.last_command.take() {
+                Some(command) => command,
+                None => return String::new(),
+            },
+            line => line.to_string(),
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match run_command(chip8, &words) {
+            Ok(output) => output,
+            Err(message) => message,
+        };
+        self.last_command = Some(line);
+
+        // Stops caused by the command are already described by its output
+        if chip8.is_paused() {
+            chip8.debugger_mut().take_stop_reason();
+        }
+        output
+    }
+}
+
+/// Describes where the machine stopped and why, for frontends to print on each stop
+pub fn stop_message(chip8: &Chip8, reason: StopReason) -> String {
+    format!("stopped: {}\n{}", reason, current_instruction(chip8))
+}
+
+/// Address, bytes and mnemonic of the instruction at pc
+pub fn current_instruction(chip8: &Chip8) -> String {
+    let pc = chip8.pc();
+    match chip8.memory().get(pc..) {
+        Some(bytes) if !bytes.is_empty() => {
+            let disassembler = Disassembler::new(Syntax::Conventional, chip8.platform());
+            let labels = chip8.debugger().symbols().names();
+            let instruction = disassembler.decode_with_labels(bytes, pc, labels);
+            disassembler::listing_with_labels(&[instruction], Syntax::Conventional, labels)
+        }
+        _ => format!("{:#06x}: out of memory", pc),
+    }
+}
+
+fn run_command(chip8: &mut Chip8, words: &[&str]) -> Result<String, String> {
+    let (command, args) = match words.split_first() {
+        Some((command, args)) => (*command, args),
+        None => return Ok(String::new()),
+    };
+    let symbols = chip8.debugger().symbols().clone();
+
+    match (command, args) {
+        ("c", []) | ("continue", []) => {
+            chip8.resume();
+            Ok(String::new())
+        }
+        ("p", []) | ("pause", []) => {
+            chip8.pause();
+            Ok(current_instruction(chip8))
+        }
+        ("s", _) | ("step", _) if args.len() <= 1 => {
+            let count = match args.first() {
+                Some(count) => parse_number(count)?,
+                None => 1,
+            };
+            for _ in 0..count {
+                chip8.step_into().map_err(|error| error.to_string())?;
+            }
+            Ok(current_instruction(chip8))
+        }
+        ("n", []) | ("next", []) => {
+            chip8.step_over().map_err(|error| error.to_string())?;
+            if chip8.is_paused() {
+                Ok(current_instruction(chip8))
+            } else {
+                Ok(String::new())
+            }
+        }
+        ("o", []) | ("finish", []) => {
+            if chip8.step_out() {
+                Ok(String::new())
+            } else {
+                Err("not inside a subroutine".to_string())
+            }
+        }
+        ("u", [address]) | ("until", [address]) => {
+            chip8.run_to(symbols.resolve(address)?);
+            Ok(String::new())
+        }
+        ("b", [address]) | ("break", [address]) => {
+            let address = symbols.resolve(address)?;
+            if chip8.debugger_mut().add_breakpoint(address) {
+                Ok(format!("breakpoint at {}", symbols.describe(address)))
+            } else {
+                let address = symbols.describe(address);
+                Err(format!("there is already a breakpoint at {}", address))
+            }
+        }
+        ("d", []) | ("delete", []) => {
+            chip8.debugger_mut().clear_breakpoints();
+            Ok("deleted all breakpoints".to_string())
+        }
+        ("d", [address]) | ("delete", [address]) => {
+            let address = symbols.resolve(address)?;
+            if chip8.debugger_mut().remove_breakpoint(address) {
+                Ok(format!(
+                    "deleted breakpoint at {}",
+                    symbols.describe(address)
+                ))
+            } else {
+                Err(format!("no breakpoint at {}", symbols.describe(address)))
+            }
+        }
+        ("bl", []) | ("breakpoints", []) => {
+            let breakpoints: Vec<String> = chip8
+                .debugger()
+                .breakpoints()
+                .map(|address| symbols.describe(address))
+                .collect();
+            if breakpoints.is_empty() {
+                Ok("no breakpoints".to_string())
+            } else {
+                Ok(breakpoints.join("\n"))
+            }
+        }
+        ("w", _) | ("watch", _) => {
+            let watchpoint = parse_watchpoint(args, &symbols)?;
+            let position = chip8.debugger_mut().add_watchpoint(watchpoint);
+            Ok(format!("watchpoint {}: {}", position, watchpoint))
+        }
+        ("wl", []) | ("watchpoints", []) => {
+            let watchpoints: Vec<String> = chip8
+                .debugger()
+                .watchpoints()
+                .iter()
+                .enumerate()
+                .map(|(position, watchpoint)| format!("{}: {}", position, watchpoint))
+                .collect();
+            if watchpoints.is_empty() {
+                Ok("no watchpoints".to_string())
+            } else {
+                Ok(watchpoints.join("\n"))
+            }
+        }
+        ("uw", []) | ("unwatch", []) => {
+            chip8.debugger_mut().clear_watchpoints();
+            Ok("deleted all watchpoints".to_string())
+        }
+        ("uw", [position]) | ("unwatch", [position]) => {
+            let position = parse_number(position)?;
+            match chip8.debugger_mut().remove_watchpoint(position) {
+                Some(watchpoint) => Ok(format!("deleted watchpoint {}", watchpoint)),
+                None => Err(format!("no watchpoint {}", position)),
+            }
+        }
+        ("r", []) | ("regs", []) => Ok(registers(chip8)),
+        ("x", _) | ("mem", _) if !args.is_empty() && args.len() <= 2 => {
+            let address = symbols.resolve(args[0])?;
+            let length = match args.get(1) {
+                Some(length) => parse_number(length)?,
+                None => DEFAULT_DUMP_LENGTH,
+            };
+            memory_dump(chip8, address, length)
+        }
+        ("bt", []) | ("stack", []) => Ok(backtrace(chip8)),
+        ("set", [register, value]) => {
+            set_register(chip8, register, symbols.resolve(value)?)?;
+            Ok(registers(chip8))
+        }
+        ("h", []) | ("help", []) => Ok(HELP.to_string()),
+        _ => Err(format!("invalid command '{}', try 'help'", words.join(" "))),
+    }
+}
+
+fn registers(chip8: &Chip8) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "pc: {:#06x}  I: {:#06x}  SP: {}  DT: {}  ST: {}",
+        chip8.pc(),
+        chip8.index(),
+        chip8.stack_pointer(),
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    );
+    for (row, registers) in chip8.registers().chunks(8).enumerate() {
+        let line: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X}: {:02x}", row * 8 + i, value))
+            .collect();
+        let _ = writeln!(out, "{}", line.join("  "));
+    }
+    out.push_str(&current_instruction(chip8));
+
+    out
+}
+
+fn memory_dump(chip8: &Chip8, address: usize, length: usize) -> Result<String, String> {
+    let memory = chip8.memory();
+    let end = address.saturating_add(length).min(memory.len());
+    if address >= end {
+        return Err(format!("{:#06x} is out of memory", address));
+    }
+
+    let lines: Vec<String> = memory[address..end]
+        .chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("{:04x}: {}", address + row * 16, bytes.join(" "))
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+/// pc followed by the address of each subroutine call, from the innermost to the outermost
+fn backtrace(chip8: &Chip8) -> String {
+    let symbols = chip8.debugger().symbols();
+    let mut lines = vec![format!("#0 {}", symbols.describe(chip8.pc()))];
+
+    let stack = &chip8.stack()[..chip8.stack_pointer()];
+    for (depth, &address) in stack.iter().rev().enumerate() {
+        lines.push(format!(
+            "#{} {}",
+            depth + 1,
+            symbols.describe(address as usize)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn set_register(chip8: &mut Chip8, register: &str, value: usize) -> Result<(), String> {
+    let register = register.to_lowercase();
+    let byte = || u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", value));
+
+    match register.as_str() {
+        "pc" => chip8.set_pc(value),
+        "i" => chip8.set_index(value),
+        "dt" => chip8.set_delay_timer(byte()?),
+        "st" => chip8.set_sound_timer(byte()?),
+        _ => {
+            let x = register
+                .strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .ok_or_else(|| format!("unknown register '{}'", register))?;
+            chip8.set_register(x, byte()?);
+        }
+    }
+    Ok(())
+}
+
+/// Parses the arguments of the watch command
+fn parse_watchpoint(args: &[&str], symbols: &SymbolTable) -> Result<Watchpoint, String> {
+    let invalid = || format!("invalid watchpoint '{}', try 'help'", args.join(" "));
+    let range = |args: &[&str]| -> Result<(usize, usize), String> {
+        match args {
+            [address] => Ok((symbols.resolve(address)?, symbols.resolve(address)?)),
+            [start, end] => Ok((symbols.resolve(start)?, symbols.resolve(end)?)),
+            _ => Err(invalid()),
+        }
+    };
+
+    let (kind, args) = args.split_first().ok_or_else(invalid)?;
+    let kind = kind.to_lowercase();
+    let access = match kind.as_str() {
+        "r" => Some(Access::Read),
+        "w" => Some(Access::Write),
+        "rw" => Some(Access::ReadWrite),
+        _ => None,
+    };
+
+    if let Some(access) = access {
+        let (start, end) = range(args)?;
+        return Ok(Watchpoint::Memory { access, start, end });
+    }
+    if kind == "i" {
+        let (start, end) = range(args)?;
+        return Ok(Watchpoint::Index { start, end });
+    }
+
+    let register = kind
+        .strip_prefix('v')
+        .filter(|x| x.len() == 1)
+        .and_then(|x| u8::from_str_radix(x, 16).ok())
+        .ok_or_else(invalid)?;
+    match args {
+        [] => Ok(Watchpoint::RegisterChange(register)),
+        [operator, value] => {
+            let comparison = Comparison::ALL
+                .iter()
+                .find(|(_, known)| known == operator)
+                .map(|&(comparison, _)| comparison)
+                .ok_or_else(invalid)?;
+            let value = u8::try_from(parse_number(value)?)
+                .map_err(|_| format!("{} doesn't fit in a byte", value))?;
+            Ok(Watchpoint::Register {
+                register,
+                comparison,
+                value,
+            })
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_console() {
+        let mut chip8 = Chip8::new();
+        // 0x200: CALL 0x206 / 0x202: ADD V1, 1 / 0x204: JP 0x200 / 0x206: ADD V0, 1 / RET
+        let program = [0x22, 0x06, 0x71, 0x01, 0x12, 0x00, 0x70, 0x01, 0x00, 0xEE];
+        chip8.load_program_bytes(&program).unwrap();
+        let mut console = DebugConsole::new();
+
+        assert_eq!(
+            console.execute(&mut chip8, "b 0x208"),
+            "breakpoint at 0x0208"
+        );
+        assert_eq!(console.execute(&mut chip8, "bl"), "0x0208");
+        assert_eq!(console.execute(&mut chip8, "c"), "");
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_paused());
+        assert_eq!(
+            stop_message(&chip8, chip8.debugger().stop_reason().unwrap()),
+            "stopped: breakpoint at 0x208\n0x0208: 00ee      RET"
+        );
+        assert_eq!(console.execute(&mut chip8, "bt"), "#0 0x0208\n#1 0x0200");
+
+        assert_eq!(
+            console.execute(&mut chip8, "s"),
+            "0x0202: 7101      ADD V1, #01"
+        );
+        assert_eq!(console.execute(&mut chip8, ""), "0x0204: 1200      JP #200");
+        assert_eq!(
+            console.execute(&mut chip8, "step 2"),
+            "0x0206: 7001      ADD V0, #01"
+        );
+
+        assert!(console
+            .execute(&mut chip8, "set v3 0x10")
+            .contains("V3: 10"));
+        assert_eq!(chip8.register(0x3), 0x10);
+        assert_eq!(
+            console.execute(&mut chip8, "x 0x200 4"),
+            "0200: 22 06 71 01"
+        );
+
+        assert_eq!(
+            console.execute(&mut chip8, "watch w 0x300 0x30f"),
+            "watchpoint 0: write 0x0300-0x030f"
+        );
+        assert_eq!(
+            console.execute(&mut chip8, "w v3 == 0x10"),
+            "watchpoint 1: V3 == 0x10"
+        );
+        assert_eq!(
+            console.execute(&mut chip8, "wl"),
+            "0: write 0x0300-0x030f\n1: V3 == 0x10"
+        );
+        assert_eq!(
+            console.execute(&mut chip8, "uw 0"),
+            "deleted watchpoint write 0x0300-0x030f"
+        );
+        assert_eq!(
+            console.execute(&mut chip8, "w v3 ~ 1"),
+            "invalid watchpoint 'v3 ~ 1', try 'help'"
+        );
+
+        assert_eq!(console.execute(&mut chip8, "d"), "deleted all breakpoints");
+        assert_eq!(
+            console.execute(&mut chip8, "b nowhere"),
+            "unknown symbol 'nowhere'"
+        );
+        assert_eq!(
+            console.execute(&mut chip8, "jump"),
+            "invalid command 'jump', try 'help'"
+        );
+
+        let symbols = "0x0200 main\n0x0206 sub".parse().unwrap();
+        chip8.debugger_mut().set_symbols(symbols);
+        assert_eq!(
+            console.execute(&mut chip8, "b sub"),
+            "breakpoint at 0x0206 <sub>"
+        );
+        assert_eq!(
+            console.execute(&mut chip8, "bt"),
+            "#0 0x0206 <sub>\n#1 0x0200 <main>"
+        );
+        assert_eq!(
+            current_instruction(&chip8),
+            "sub:\n0x0206: 7001      ADD V0, #01"
+        );
+    }
+}
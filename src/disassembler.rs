@@ -0,0 +1,692 @@
+use crate::chip8::Platform;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Mnemonics flavour of the disassembly
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Cowgod's mnemonics, e.g. LD V1, #05 and DRW V0, V1, 5
+    #[default]
+    Conventional,
+    /// Octo statements, e.g. v1 := 0x05 and sprite v0 v1 5
+    Octo,
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Syntax::Conventional => write!(f, "conventional"),
+            Syntax::Octo => write!(f, "octo"),
+        }
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "conventional" => Ok(Syntax::Conventional),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!(
+                "unknown syntax '{}', expected conventional or octo",
+                name
+            )),
+        }
+    }
+}
+
+/// A decoded instruction, or data bytes when they don't decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: usize,
+    /// 2 bytes, 4 for the XO-CHIP F000 NNNN, or 1 for a trailing odd byte
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// Whether text describes data rather than an instruction
+    pub is_data: bool,
+}
+
+/// Turns bytecode into mnemonics
+///
+/// Opcodes the platform doesn't support are shown as data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disassembler {
+    pub syntax: Syntax,
+    pub platform: Platform,
+}
+
+impl Default for Disassembler {
+    /// Conventional syntax, decoding everything up to XO-CHIP
+    fn default() -> Self {
+        Disassembler {
+            syntax: Syntax::Conventional,
+            platform: Platform::XoChip,
+        }
+    }
+}
+
+/// Disassembles bytes loaded at base_address, with the default Disassembler
+pub fn disassemble(bytes: &[u8], base_address: usize) -> Vec<Instruction> {
+    Disassembler::default().disassemble(bytes, base_address)
+}
+
+impl Disassembler {
+    pub fn new(syntax: Syntax, platform: Platform) -> Self {
+        Disassembler { syntax, platform }
+    }
+
+    /// Decodes bytes linearly, as if they were all code
+    pub fn disassemble(&self, bytes: &[u8], base_address: usize) -> Vec<Instruction> {
+        self.disassemble_with_labels(bytes, base_address, &BTreeMap::new())
+    }
+
+    /// Same as disassemble(), naming the addresses found in labels
+    pub fn disassemble_with_labels(
+        &self,
+        bytes: &[u8],
+        base_address: usize,
+        labels: &BTreeMap<usize, String>,
+    ) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+
+        while offset < bytes.len() {
+            let address = base_address + offset;
+            let instruction = self.decode_with_labels(&bytes[offset..], address, labels);
+            offset += instruction.bytes.len();
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    /// Decodes the instruction at the start of bytes, which must not be empty
+    pub fn decode(&self, bytes: &[u8], address: usize) -> Instruction {
+        self.decode_with_labels(bytes, address, &BTreeMap::new())
+    }
+
+    /// Same as decode(), naming the addresses found in labels
+    pub fn decode_with_labels(
+        &self,
+        bytes: &[u8],
+        address: usize,
+        labels: &BTreeMap<usize, String>,
+    ) -> Instruction {
+        let opcode = match bytes {
+            [high, low, ..] => (*high as u16) << 8 | *low as u16,
+            _ => return self.data(&bytes[..1], address),
+        };
+
+        if opcode == 0xF000 && self.platform.supports_xochip() {
+            return match bytes.get(2..4) {
+                Some(long) => {
+                    let nnnn = (long[0] as u16) << 8 | long[1] as u16;
+                    let text = match self.syntax {
+                        Syntax::Conventional => {
+                            format!("LD I, LONG {}", self.address(nnnn as usize, 4, labels))
+                        }
+                        Syntax::Octo => {
+                            format!("i := long {}", self.address(nnnn as usize, 4, labels))
+                        }
+                    };
+                    Instruction {
+                        address,
+                        bytes: bytes[..4].to_vec(),
+                        text,
+                        is_data: false,
+                    }
+                }
+                None => self.data(&bytes[..2], address),
+            };
+        }
+
+        let text = match self.syntax {
+            Syntax::Conventional => self.conventional(opcode, labels),
+            Syntax::Octo => self.octo(opcode, labels),
+        };
+        match text {
+            Some(text) => Instruction {
+                address,
+                bytes: bytes[..2].to_vec(),
+                text,
+                is_data: false,
+            },
+            None => self.data(&bytes[..2], address),
+        }
+    }
+
+    /// Label of address, or address itself with digits hexadecimal digits
+    fn address(&self, address: usize, digits: usize, labels: &BTreeMap<usize, String>) -> String {
+        match (labels.get(&address), self.syntax) {
+            (Some(label), _) => label.clone(),
+            (None, Syntax::Conventional) => format!("#{:0digits$X}", address, digits = digits),
+            (None, Syntax::Octo) => format!("0x{:0digits$X}", address, digits = digits),
+        }
+    }
+
+    fn data(&self, bytes: &[u8], address: usize) -> Instruction {
+        let text = match self.syntax {
+            Syntax::Conventional => {
+                let bytes: Vec<String> =
+                    bytes.iter().map(|byte| format!("#{:02X}", byte)).collect();
+                format!(".byte {}", bytes.join(", "))
+            }
+            Syntax::Octo => {
+                let bytes: Vec<String> =
+                    bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                bytes.join(" ")
+            }
+        };
+
+        Instruction {
+            address,
+            bytes: bytes.to_vec(),
+            text,
+            is_data: true,
+        }
+    }
+
+    fn conventional(&self, opcode: u16, labels: &BTreeMap<usize, String>) -> Option<String> {
+        let (f, x, y, n) = nibbles(opcode);
+        let kk = opcode & 0x00FF;
+        let nnn = opcode & 0x0FFF;
+        let address = self.address(nnn as usize, 3, labels);
+        let schip = self.platform.supports_superchip();
+        let xochip = self.platform.supports_xochip();
+
+        let text = match (f, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+            (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+            (0x0, 0x0, 0xC, _) if schip => format!("SCD {}", n),
+            (0x0, 0x0, 0xD, _) if xochip => format!("SCU {}", n),
+            (0x0, 0x0, 0xF, 0xB) if schip => "SCR".to_string(),
+            (0x0, 0x0, 0xF, 0xC) if schip => "SCL".to_string(),
+            (0x0, 0x0, 0xF, 0xD) if schip => "EXIT".to_string(),
+            (0x0, 0x0, 0xF, 0xE) if schip => "LOW".to_string(),
+            (0x0, 0x0, 0xF, 0xF) if schip => "HIGH".to_string(),
+            (0x0, _, _, _) if nnn != 0 => format!("SYS #{:03X}", nnn),
+            (0x1, _, _, _) => format!("JP {}", address),
+            (0x2, _, _, _) => format!("CALL {}", address),
+            (0x3, _, _, _) => format!("SE V{:X}, #{:02X}", x, kk),
+            (0x4, _, _, _) => format!("SNE V{:X}, #{:02X}", x, kk),
+            (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+            (0x5, _, _, 0x2) if xochip => format!("LD [I], V{:X} - V{:X}", x, y),
+            (0x5, _, _, 0x3) if xochip => format!("LD V{:X} - V{:X}, [I]", x, y),
+            (0x6, _, _, _) => format!("LD V{:X}, #{:02X}", x, kk),
+            (0x7, _, _, _) => format!("ADD V{:X}, #{:02X}", x, kk),
+            (0x8, _, _, _) => {
+                let mnemonic = match n {
+                    0x0 => "LD",
+                    0x1 => "OR",
+                    0x2 => "AND",
+                    0x3 => "XOR",
+                    0x4 => "ADD",
+                    0x5 => "SUB",
+                    0x6 => "SHR",
+                    0x7 => "SUBN",
+                    0xE => "SHL",
+                    _ => return None,
+                };
+                format!("{} V{:X}, V{:X}", mnemonic, x, y)
+            }
+            (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+            (0xA, _, _, _) => format!("LD I, {}", address),
+            (0xB, _, _, _) => format!("JP V0, {}", address),
+            (0xC, _, _, _) => format!("RND V{:X}, #{:02X}", x, kk),
+            (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+            (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+            (0xF, _, 0x0, 0x1) if xochip => format!("PLANE {}", x),
+            (0xF, 0x0, 0x0, 0x2) if xochip => "AUDIO".to_string(),
+            (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+            (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+            (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+            (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+            (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+            (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+            (0xF, _, 0x3, 0x0) if schip => format!("LD HF, V{:X}", x),
+            (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+            (0xF, _, 0x3, 0xA) if xochip => format!("PITCH V{:X}", x),
+            (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+            (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+            (0xF, _, 0x7, 0x5) if schip => format!("LD R, V{:X}", x),
+            (0xF, _, 0x8, 0x5) if schip => format!("LD V{:X}, R", x),
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    fn octo(&self, opcode: u16, labels: &BTreeMap<usize, String>) -> Option<String> {
+        let (f, x, y, n) = nibbles(opcode);
+        let kk = opcode & 0x00FF;
+        let nnn = opcode & 0x0FFF;
+        let address = self.address(nnn as usize, 3, labels);
+        let schip = self.platform.supports_superchip();
+        let xochip = self.platform.supports_xochip();
+
+        let text = match (f, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => "clear".to_string(),
+            (0x0, 0x0, 0xE, 0xE) => "return".to_string(),
+            (0x0, 0x0, 0xC, _) if schip => format!("scroll-down {}", n),
+            (0x0, 0x0, 0xD, _) if xochip => format!("scroll-up {}", n),
+            (0x0, 0x0, 0xF, 0xB) if schip => "scroll-right".to_string(),
+            (0x0, 0x0, 0xF, 0xC) if schip => "scroll-left".to_string(),
+            (0x0, 0x0, 0xF, 0xD) if schip => "exit".to_string(),
+            (0x0, 0x0, 0xF, 0xE) if schip => "lores".to_string(),
+            (0x0, 0x0, 0xF, 0xF) if schip => "hires".to_string(),
+            (0x1, _, _, _) => format!("jump {}", address),
+            (0x2, _, _, _) => format!(":call {}", address),
+            (0x3, _, _, _) => format!("if v{:x} != 0x{:02X} then", x, kk),
+            (0x4, _, _, _) => format!("if v{:x} == 0x{:02X} then", x, kk),
+            (0x5, _, _, 0x0) => format!("if v{:x} != v{:x} then", x, y),
+            (0x5, _, _, 0x2) if xochip => format!("save v{:x} - v{:x}", x, y),
+            (0x5, _, _, 0x3) if xochip => format!("load v{:x} - v{:x}", x, y),
+            (0x6, _, _, _) => format!("v{:x} := 0x{:02X}", x, kk),
+            (0x7, _, _, _) => format!("v{:x} += 0x{:02X}", x, kk),
+            (0x8, _, _, _) => {
+                let operator = match n {
+                    0x0 => ":=",
+                    0x1 => "|=",
+                    0x2 => "&=",
+                    0x3 => "^=",
+                    0x4 => "+=",
+                    0x5 => "-=",
+                    0x6 => ">>=",
+                    0x7 => "=-",
+                    0xE => "<<=",
+                    _ => return None,
+                };
+                format!("v{:x} {} v{:x}", x, operator, y)
+            }
+            (0x9, _, _, 0x0) => format!("if v{:x} == v{:x} then", x, y),
+            (0xA, _, _, _) => format!("i := {}", address),
+            (0xB, _, _, _) => format!("jump0 {}", address),
+            (0xC, _, _, _) => format!("v{:x} := random 0x{:02X}", x, kk),
+            (0xD, _, _, _) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            (0xE, _, 0x9, 0xE) => format!("if v{:x} -key then", x),
+            (0xE, _, 0xA, 0x1) => format!("if v{:x} key then", x),
+            (0xF, _, 0x0, 0x1) if xochip => format!("plane {}", x),
+            (0xF, 0x0, 0x0, 0x2) if xochip => "audio".to_string(),
+            (0xF, _, 0x0, 0x7) => format!("v{:x} := delay", x),
+            (0xF, _, 0x0, 0xA) => format!("v{:x} := key", x),
+            (0xF, _, 0x1, 0x5) => format!("delay := v{:x}", x),
+            (0xF, _, 0x1, 0x8) => format!("buzzer := v{:x}", x),
+            (0xF, _, 0x1, 0xE) => format!("i += v{:x}", x),
+            (0xF, _, 0x2, 0x9) => format!("i := hex v{:x}", x),
+            (0xF, _, 0x3, 0x0) if schip => format!("i := bighex v{:x}", x),
+            (0xF, _, 0x3, 0x3) => format!("bcd v{:x}", x),
+            (0xF, _, 0x3, 0xA) if xochip => format!("pitch := v{:x}", x),
+            (0xF, _, 0x5, 0x5) => format!("save v{:x}", x),
+            (0xF, _, 0x6, 0x5) => format!("load v{:x}", x),
+            (0xF, _, 0x7, 0x5) if schip => format!("saveflags v{:x}", x),
+            (0xF, _, 0x8, 0x5) if schip => format!("loadflags v{:x}", x),
+            _ => return None,
+        };
+        Some(text)
+    }
+}
+
+/// Result of Disassembler::trace()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub syntax: Syntax,
+    /// Code and data in address order, data being split into single bytes
+    pub lines: Vec<Instruction>,
+    /// Names of the subroutines, jump targets and data pointed to by I
+    pub labels: BTreeMap<usize, String>,
+    pub subroutines: BTreeSet<usize>,
+    /// Addresses of the BNNN instructions, whose targets can't be traced
+    pub computed_jumps: BTreeSet<usize>,
+}
+
+impl Disassembler {
+    /// Follows the control flow from entry_points, telling code apart from data
+    ///
+    /// Jumps, calls and both outcomes of skips are followed, returns and exits end a path.
+    /// Code only reached through BNNN computed jumps is seen as data, unless its address
+    /// is given as an extra entry point
+    pub fn trace(&self, bytes: &[u8], base_address: usize, entry_points: &[usize]) -> Trace {
+        self.trace_with_labels(bytes, base_address, entry_points, &BTreeMap::new())
+    }
+
+    /// Same as trace(), known_labels replacing the generated names
+    pub fn trace_with_labels(
+        &self,
+        bytes: &[u8],
+        base_address: usize,
+        entry_points: &[usize],
+        known_labels: &BTreeMap<usize, String>,
+    ) -> Trace {
+        let end = base_address + bytes.len();
+        let in_rom = |address: usize| address >= base_address && address < end;
+        let decode = |address: usize| self.decode(&bytes[address - base_address..], address);
+
+        let mut code = BTreeMap::new();
+        let mut jump_targets = BTreeSet::new();
+        let mut subroutines = BTreeSet::new();
+        let mut data_references = BTreeSet::new();
+        let mut computed_jumps = BTreeSet::new();
+        let mut pending = entry_points.to_vec();
+
+        while let Some(address) = pending.pop() {
+            if !in_rom(address) || code.contains_key(&address) {
+                continue;
+            }
+            let instruction = decode(address);
+            if instruction.is_data {
+                continue;
+            }
+
+            let next = address + instruction.bytes.len();
+            let opcode = (instruction.bytes[0] as u16) << 8 | instruction.bytes[1] as u16;
+            let (f, _, _, n) = nibbles(opcode);
+            let nnn = (opcode & 0x0FFF) as usize;
+            code.insert(address, instruction);
+
+            match (f, n) {
+                _ if opcode == 0x00EE || opcode == 0x00FD => (),
+                (0x1, _) => {
+                    jump_targets.insert(nnn);
+                    pending.push(nnn);
+                }
+                (0x2, _) => {
+                    subroutines.insert(nnn);
+                    pending.push(nnn);
+                    pending.push(next);
+                }
+                (0xA, _) => {
+                    data_references.insert(nnn);
+                    pending.push(next);
+                }
+                (0xB, _) => {
+                    computed_jumps.insert(address);
+                }
+                (0x3, _) | (0x4, _) | (0x5, 0x0) | (0x9, _) | (0xE, _) => {
+                    let skipped = if in_rom(next) {
+                        decode(next).bytes.len()
+                    } else {
+                        2
+                    };
+                    pending.push(next);
+                    pending.push(next + skipped);
+                }
+                (0xF, _) if next - address == 4 => {
+                    let nnnn =
+                        (code[&address].bytes[2] as usize) << 8 | code[&address].bytes[3] as usize;
+                    data_references.insert(nnnn);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        let mut labels = BTreeMap::new();
+        let names = [
+            (&data_references, "data"),
+            (&jump_targets, "label"),
+            (&subroutines, "sub"),
+        ];
+        for (addresses, prefix) in names.iter() {
+            for &address in addresses.iter().filter(|&&address| in_rom(address)) {
+                labels.insert(address, format!("{}_{:03x}", prefix, address));
+            }
+        }
+        labels.extend(known_labels.clone());
+
+        let mut lines = Vec::new();
+        let mut address = base_address;
+        while address < end {
+            let line = match code.get(&address) {
+                Some(_) => {
+                    self.decode_with_labels(&bytes[address - base_address..], address, &labels)
+                }
+                None => self.data(&bytes[address - base_address..][..1], address),
+            };
+            address += line.bytes.len();
+            lines.push(line);
+        }
+
+        Trace {
+            syntax: self.syntax,
+            lines,
+            labels,
+            subroutines,
+            computed_jumps,
+        }
+    }
+}
+
+impl Trace {
+    /// Formats the trace like listing(), adding labels, a header before each subroutine, the
+    /// pixels of data bytes and a warning after computed jumps
+    pub fn listing(&self) -> String {
+        let mut lines = Vec::with_capacity(self.lines.len());
+        let comment = match self.syntax {
+            Syntax::Conventional => ";",
+            Syntax::Octo => "#",
+        };
+
+        for line in &self.lines {
+            if self.subroutines.contains(&line.address) {
+                lines.push(String::new());
+                lines.push(format!("{} subroutine", comment));
+            }
+            if let Some(label) = self.labels.get(&line.address) {
+                lines.push(label_line(label, self.syntax));
+            }
+
+            let note = if line.is_data {
+                let pixels: String = (0..8)
+                    .map(|bit| {
+                        if line.bytes[0] & (0x80 >> bit) != 0 {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                Some(pixels)
+            } else if self.computed_jumps.contains(&line.address) {
+                Some("computed jump, its targets need entry point hints".to_string())
+            } else {
+                None
+            };
+            lines.push(listing_line(line, self.syntax, note.as_deref()));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Formats instructions one per line, along with their address and bytes
+///
+/// Octo listings are valid Octo source, addresses and bytes being comments
+pub fn listing(instructions: &[Instruction], syntax: Syntax) -> String {
+    listing_with_labels(instructions, syntax, &BTreeMap::new())
+}
+
+/// Same as listing(), with a label line before each labelled instruction
+pub fn listing_with_labels(
+    instructions: &[Instruction],
+    syntax: Syntax,
+    labels: &BTreeMap<usize, String>,
+) -> String {
+    let mut lines = Vec::with_capacity(instructions.len());
+
+    for instruction in instructions {
+        if let Some(label) = labels.get(&instruction.address) {
+            lines.push(label_line(label, syntax));
+        }
+        lines.push(listing_line(instruction, syntax, None));
+    }
+    lines.join("\n")
+}
+
+fn label_line(label: &str, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Conventional => format!("{}:", label),
+        Syntax::Octo => format!(": {}", label),
+    }
+}
+
+fn listing_line(instruction: &Instruction, syntax: Syntax, note: Option<&str>) -> String {
+    let bytes: String = instruction
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let line = match syntax {
+        Syntax::Conventional => format!(
+            "{:#06x}: {:<8}  {}",
+            instruction.address, bytes, instruction.text
+        ),
+        Syntax::Octo => format!(
+            "{:<28}# {:#06x}: {}",
+            instruction.text, instruction.address, bytes
+        ),
+    };
+    match (note, syntax) {
+        (Some(note), Syntax::Conventional) => format!("{:<36}; {}", line, note),
+        (Some(note), Syntax::Octo) => format!("{}  {}", line, note),
+        (None, _) => line,
+    }
+}
+
+fn nibbles(opcode: u16) -> (u8, u8, u8, u8) {
+    (
+        (opcode >> 12) as u8,
+        (opcode >> 8 & 0xF) as u8,
+        (opcode >> 4 & 0xF) as u8,
+        (opcode & 0xF) as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(instructions: &[Instruction]) -> Vec<&str> {
+        instructions
+            .iter()
+            .map(|instruction| instruction.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let program = [
+            0x61, 0x05, 0xD0, 0x15, 0x22, 0xA4, 0x80, 0x16, 0xF3, 0x65, 0x00, 0xFF, 0xF0, 0x00,
+            0x12, 0x34, 0xFF, 0xFF, 0xAB,
+        ];
+        let instructions = disassemble(&program, 0x200);
+
+        assert_eq!(
+            texts(&instructions),
+            [
+                "LD V1, #05",
+                "DRW V0, V1, 5",
+                "CALL #2A4",
+                "SHR V0, V1",
+                "LD V3, [I]",
+                "HIGH",
+                "LD I, LONG #1234",
+                ".byte #FF, #FF",
+                ".byte #AB",
+            ]
+        );
+        assert_eq!(instructions[6].address, 0x20C);
+        assert_eq!(instructions[6].bytes, [0xF0, 0x00, 0x12, 0x34]);
+        assert!(instructions[7].is_data);
+
+        let octo = Disassembler::new(Syntax::Octo, Platform::XoChip);
+        assert_eq!(
+            texts(&octo.disassemble(&program, 0x200)),
+            [
+                "v1 := 0x05",
+                "sprite v0 v1 5",
+                ":call 0x2A4",
+                "v0 >>= v1",
+                "load v3",
+                "hires",
+                "i := long 0x1234",
+                "0xFF 0xFF",
+                "0xAB",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_platform_opcodes() {
+        let program = [0x00, 0xFF, 0xF1, 0x30, 0xF0, 0x00, 0x12, 0x34];
+
+        let vip = Disassembler::new(Syntax::Conventional, Platform::CosmacVip);
+        assert_eq!(
+            texts(&vip.disassemble(&program, 0x200)),
+            ["SYS #0FF", ".byte #F1, #30", ".byte #F0, #00", "JP #234"]
+        );
+
+        let schip = Disassembler::new(Syntax::Conventional, Platform::SuperChip);
+        assert_eq!(
+            texts(&schip.disassemble(&program[..4], 0x200)),
+            ["HIGH", "LD HF, V1"]
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let program = [
+            0xA2, 0x08, // LD I, data_208
+            0x22, 0x06, // CALL sub_206
+            0x12, 0x04, // JP label_204
+            0x00, 0xEE, // RET
+            0xFF, 0x81,
+        ];
+        let trace = Disassembler::default().trace(&program, 0x200, &[0x200]);
+
+        assert_eq!(
+            trace.listing(),
+            "\
+0x0200: a208      LD I, data_208
+0x0202: 2206      CALL sub_206
+label_204:
+0x0204: 1204      JP label_204
+
+; subroutine
+sub_206:
+0x0206: 00ee      RET
+data_208:
+0x0208: ff        .byte #FF         ; ########
+0x0209: 81        .byte #81         ; #......#"
+        );
+
+        // 0x200: JP V0, #204 / 0x202: data / 0x204: CLS / 0x206: JP #206
+        let program = [0xB2, 0x04, 0xFF, 0xFF, 0x00, 0xE0, 0x12, 0x06];
+        let trace = Disassembler::default().trace(&program, 0x200, &[0x200]);
+        assert_eq!(trace.computed_jumps.iter().collect::<Vec<_>>(), [&0x200]);
+        assert!(trace.lines[1..].iter().all(|line| line.is_data));
+
+        let trace = Disassembler::default().trace(&program, 0x200, &[0x200, 0x204]);
+        assert_eq!(texts(&trace.lines[3..]), ["CLS", "JP label_206"]);
+    }
+
+    #[test]
+    fn test_listing() {
+        let instructions = disassemble(&[0x6A, 0x02, 0x00], 0x200);
+
+        assert_eq!(
+            listing(&instructions, Syntax::Conventional),
+            "0x0200: 6a02      LD VA, #02\n0x0202: 00        .byte #00"
+        );
+
+        let labels: BTreeMap<usize, String> =
+            vec![(0x200, "main".to_string())].into_iter().collect();
+        let instructions = Disassembler::new(Syntax::Octo, Platform::XoChip)
+            .disassemble_with_labels(&[0x12, 0x00], 0x200, &labels);
+        assert_eq!(
+            listing_with_labels(&instructions, Syntax::Octo, &labels),
+            ": main\njump main                   # 0x0200: 1200"
+        );
+    }
+}
@@ -0,0 +1,687 @@
+//! GDB remote serial protocol stub, letting debuggers attach to a Chip8 over TCP
+//!
+//! Registers are numbered V0-VF (0-15, one byte each), I and PC (16 and 17, two little
+//! endian bytes each), then SP, DT and ST (18-20, one byte each). They are also described
+//! by the target.xml sent through qXfer, for debuggers that read it
+
+use crate::chip8::{Access, Chip8, StopReason, Watchpoint};
+
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+
+const REGISTER_COUNT: usize = 21;
+
+/// Largest packet accepted, advertised in the qSupported reply
+const PACKET_SIZE: usize = 0x1000;
+
+/// Signals sent in stop replies
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Error replies, errno values as gdbserver sends them
+const EFAULT: &str = "E0e";
+const EINVAL: &str = "E16";
+
+/// State of the debugger connection, returned by GdbStub::poll()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    Attached,
+    /// The debugger detached or closed the connection, the machine keeps running
+    Detached,
+    /// The debugger asked to kill the program, frontends are expected to quit
+    Killed,
+}
+
+/// What the debugger sent, once a whole packet arrived
+enum Incoming {
+    Packet(String),
+    /// Ctrl+C, sent outside of packets
+    Interrupt,
+}
+
+/// Serves a single debugger connection, controlling the Chip8 through its Debugger
+///
+/// Frontends call poll() once per frame along with Chip8::run_frame(), so breakpoints and
+/// steps behave like the ones set from the debug console
+pub struct GdbStub<S = TcpStream> {
+    stream: S,
+    input: Vec<u8>,
+    no_ack: bool,
+    /// Whether the debugger resumed the machine and waits for it to stop
+    running: bool,
+    session: Session,
+}
+
+impl GdbStub<TcpStream> {
+    /// Waits for a debugger to connect to address, e.g. 127.0.0.1:1234
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: Read + Write> GdbStub<S> {
+    /// Creates a stub talking over stream, which must not block on reads
+    pub fn new(stream: S) -> Self {
+        GdbStub {
+            stream,
+            input: Vec::new(),
+            no_ack: false,
+            running: false,
+            session: Session::Attached,
+        }
+    }
+
+    /// Handles the packets received since the last call, and tells the debugger once the
+    /// machine it resumed stops
+    pub fn poll(&mut self, chip8: &mut Chip8) -> io::Result<Session> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.session = Session::Detached;
+                    break;
+                }
+                Ok(length) => self.input.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error),
+            }
+        }
+
+        while self.session == Session::Attached {
+            match self.next_incoming()? {
+                Some(Incoming::Packet(packet)) => {
+                    if let Some(reply) = self.handle(chip8, &packet) {
+                        self.send(&reply)?;
+                    }
+                }
+                Some(Incoming::Interrupt) => chip8.pause(),
+                None => break,
+            }
+        }
+
+        if self.session == Session::Detached {
+            chip8.resume();
+        } else if self.running && chip8.is_paused() {
+            self.running = false;
+            let reply = stop_reply(chip8.debugger().stop_reason());
+            self.send(&reply)?;
+        }
+        Ok(self.session)
+    }
+
+    /// Takes the next packet or interrupt out of the input, acknowledging packets
+    fn next_incoming(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            let start = match self
+                .input
+                .iter()
+                .position(|&byte| byte == b'$' || byte == 3)
+            {
+                Some(start) => start,
+                None => {
+                    // Only acknowledgements are left
+                    self.input.clear();
+                    return Ok(None);
+                }
+            };
+            if self.input[start] == 3 {
+                self.input.drain(..=start);
+                return Ok(Some(Incoming::Interrupt));
+            }
+
+            let end = match self.input[start..].iter().position(|&byte| byte == b'#') {
+                Some(end) if start + end + 2 < self.input.len() => start + end,
+                _ => return Ok(None),
+            };
+            let packet = unescape(&self.input[start + 1..end]);
+            let checksum = std::str::from_utf8(&self.input[end + 1..end + 3])
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok());
+            let valid = checksum == Some(checksum_of(&self.input[start + 1..end]));
+            self.input.drain(..end + 3);
+
+            if !self.no_ack {
+                write_all(&mut self.stream, if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(Incoming::Packet(
+                    String::from_utf8_lossy(&packet).into_owned(),
+                )));
+            }
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let mut data = Vec::with_capacity(reply.len() + 4);
+        for &byte in reply.as_bytes() {
+            if b"$#}*".contains(&byte) {
+                data.extend_from_slice(&[b'}', byte ^ 0x20]);
+            } else {
+                data.push(byte);
+            }
+        }
+        let checksum = checksum_of(&data);
+
+        let mut packet = vec![b'$'];
+        packet.append(&mut data);
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        write_all(&mut self.stream, &packet)
+    }
+
+    /// Runs a packet, returning the reply to send, if any
+    fn handle(&mut self, chip8: &mut Chip8, packet: &str) -> Option<String> {
+        let command = packet.get(..1).unwrap_or("");
+        let arguments = packet.get(1..).unwrap_or("");
+
+        let reply = match command {
+            "?" => {
+                // Asked right after connecting, the debugger expects the machine stopped
+                if !chip8.is_paused() {
+                    chip8.pause();
+                }
+                self.running = false;
+                stop_reply(chip8.debugger().stop_reason())
+            }
+            "g" => (0..REGISTER_COUNT)
+                .map(|register| to_hex(&read_register(chip8, register)))
+                .collect(),
+            "G" => match from_hex(arguments) {
+                Some(bytes) => write_registers(chip8, &bytes).to_string(),
+                None => EINVAL.to_string(),
+            },
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(register) if register < REGISTER_COUNT => {
+                    to_hex(&read_register(chip8, register))
+                }
+                _ => EINVAL.to_string(),
+            },
+            "P" => {
+                let write = arguments.split_once('=').and_then(|(register, value)| {
+                    let register = usize::from_str_radix(register, 16).ok()?;
+                    let value = from_hex(value)?;
+                    Some(write_register(chip8, register, &value))
+                });
+                match write {
+                    Some(true) => "OK".to_string(),
+                    _ => EINVAL.to_string(),
+                }
+            }
+            "m" => match parse_range(arguments) {
+                Some((address, length)) => match chip8.memory().get(address..) {
+                    Some(bytes) if !bytes.is_empty() || length == 0 => {
+                        to_hex(&bytes[..length.min(bytes.len())])
+                    }
+                    _ => EFAULT.to_string(),
+                },
+                None => EINVAL.to_string(),
+            },
+            "M" => {
+                let write = arguments.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = parse_range(range)?;
+                    let data = from_hex(data).filter(|data| data.len() == length)?;
+                    Some((address, data))
+                });
+                match write {
+                    Some((address, data)) => {
+                        let range = address
+                            .checked_add(data.len())
+                            .and_then(|end| chip8.memory_mut().get_mut(address..end));
+                        match range {
+                            Some(memory) => {
+                                memory.copy_from_slice(&data);
+                                "OK".to_string()
+                            }
+                            None => EFAULT.to_string(),
+                        }
+                    }
+                    None => EINVAL.to_string(),
+                }
+            }
+            "Z" | "z" => match parse_breakpoint(arguments) {
+                Some((kind, address, length)) => {
+                    set_breakpoint(chip8, command == "Z", kind, address, length)
+                }
+                None => EINVAL.to_string(),
+            },
+            "c" | "C" | "s" | "S" => {
+                // Only continue and step take an address, signals given to C and S are
+                // dropped since the machine has none
+                if command == "c" || command == "s" {
+                    if let Ok(address) = usize::from_str_radix(arguments, 16) {
+                        chip8.set_pc(address);
+                    }
+                }
+                self.resume(chip8, command.eq_ignore_ascii_case("s"));
+                return None;
+            }
+            "v" => return self.handle_v(chip8, packet),
+            "q" | "Q" => return Some(self.handle_query(packet)),
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "D" => {
+                self.session = Session::Detached;
+                "OK".to_string()
+            }
+            "k" => {
+                self.session = Session::Killed;
+                return None;
+            }
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Handles the v packets, replying to the unknown ones with an empty packet
+    fn handle_v(&mut self, chip8: &mut Chip8, packet: &str) -> Option<String> {
+        if packet == "vCont?" {
+            return Some("vCont;c;C;s;S".to_string());
+        }
+        if packet == "vKill" || packet.starts_with("vKill;") {
+            self.session = Session::Killed;
+            return Some("OK".to_string());
+        }
+
+        // There is a single thread, so the first action applies to it
+        let actions = match packet.strip_prefix("vCont;") {
+            Some(actions) => actions,
+            None => return Some(String::new()),
+        };
+        match actions.bytes().next() {
+            Some(b'c') | Some(b'C') => self.resume(chip8, false),
+            Some(b's') | Some(b'S') => self.resume(chip8, true),
+            _ => return Some(EINVAL.to_string()),
+        }
+        None
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;vContSupported+",
+                PACKET_SIZE
+            );
+        }
+        if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            return "OK".to_string();
+        }
+        if packet == "qAttached" {
+            return "1".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let description = target_description();
+            return match parse_range(range) {
+                Some((offset, length)) => {
+                    let chunk = description
+                        .get(offset.min(description.len())..)
+                        .unwrap_or("");
+                    if chunk.len() > length {
+                        format!("m{}", &chunk[..length])
+                    } else {
+                        format!("l{}", chunk)
+                    }
+                }
+                None => EINVAL.to_string(),
+            };
+        }
+        String::new()
+    }
+
+    /// Lets the machine run, or executes a single instruction. In both cases, the stop
+    /// reply is sent by poll() once the machine is paused
+    fn resume(&mut self, chip8: &mut Chip8, step: bool) {
+        if step {
+            // Errors stop the debugger, they are reported in the stop reply
+            let _ = chip8.step_into();
+        } else {
+            chip8.resume();
+        }
+        self.running = true;
+    }
+}
+
+/// Stop reply telling the debugger why the machine is paused
+fn stop_reply(reason: Option<StopReason>) -> String {
+    match reason {
+        Some(StopReason::Error(_)) => format!("S{:02x}", SIGILL),
+        Some(StopReason::Watchpoint(Watchpoint::Memory { access, start, .. })) => {
+            let kind = match access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+                Access::ReadWrite => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, start)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+/// Adds or removes a breakpoint (type 0 and 1) or watchpoint (type 2 to 4)
+fn set_breakpoint(
+    chip8: &mut Chip8,
+    insert: bool,
+    kind: u8,
+    address: usize,
+    length: usize,
+) -> String {
+    let debugger = chip8.debugger_mut();
+    let access = match kind {
+        0 | 1 => {
+            if insert {
+                debugger.add_breakpoint(address);
+            } else {
+                debugger.remove_breakpoint(address);
+            }
+            return "OK".to_string();
+        }
+        2 => Access::Write,
+        3 => Access::Read,
+        4 => Access::ReadWrite,
+        _ => return String::new(),
+    };
+
+    let end = match address.checked_add(length.max(1) - 1) {
+        Some(end) => end,
+        None => return EINVAL.to_string(),
+    };
+    let watchpoint = Watchpoint::Memory {
+        access,
+        start: address,
+        end,
+    };
+    if insert {
+        debugger.add_watchpoint(watchpoint);
+    } else if let Some(position) = debugger
+        .watchpoints()
+        .iter()
+        .position(|&existing| existing == watchpoint)
+    {
+        debugger.remove_watchpoint(position);
+    }
+    "OK".to_string()
+}
+
+fn read_register(chip8: &Chip8, register: usize) -> Vec<u8> {
+    match register {
+        0..=15 => vec![chip8.register(register as u8)],
+        16 => (chip8.index() as u16).to_le_bytes().to_vec(),
+        17 => (chip8.pc() as u16).to_le_bytes().to_vec(),
+        18 => vec![chip8.stack_pointer() as u8],
+        19 => vec![chip8.delay_timer()],
+        _ => vec![chip8.sound_timer()],
+    }
+}
+
+/// Returns false if register doesn't exist or value doesn't have its size
+fn write_register(chip8: &mut Chip8, register: usize, value: &[u8]) -> bool {
+    let word = |value: &[u8]| u16::from_le_bytes([value[0], value[1]]) as usize;
+
+    match (register, value.len()) {
+        (0..=15, 1) => chip8.set_register(register as u8, value[0]),
+        (16, 2) => chip8.set_index(word(value)),
+        (17, 2) => chip8.set_pc(word(value)),
+        (18, 1) if value[0] <= 16 => chip8.set_stack_pointer(value[0] as usize),
+        (19, 1) => chip8.set_delay_timer(value[0]),
+        (20, 1) => chip8.set_sound_timer(value[0]),
+        _ => return false,
+    }
+    true
+}
+
+/// Writes every register from the contents of a G packet
+fn write_registers(chip8: &mut Chip8, mut bytes: &[u8]) -> &'static str {
+    for register in 0..REGISTER_COUNT {
+        let size = read_register(chip8, register).len();
+        if bytes.len() < size || !write_register(chip8, register, &bytes[..size]) {
+            return EINVAL;
+        }
+        bytes = &bytes[size..];
+    }
+    "OK"
+}
+
+/// Register layout in the format of GDB target descriptions
+fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.chip8.core\">\n",
+    );
+    for x in 0..16 {
+        let _ = writeln!(xml, "<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", x);
+    }
+    xml.push_str(
+        "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n\
+         <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n\
+         <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\n\
+         <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\n\
+         <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\n\
+         </feature>\n\
+         </target>\n",
+    );
+    xml
+}
+
+/// Parses the ADDRESS,LENGTH arguments of memory and qXfer packets
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+/// Parses the TYPE,ADDRESS,KIND arguments of Z and z packets
+fn parse_breakpoint(text: &str) -> Option<(u8, usize, usize)> {
+    let (kind, range) = text.split_once(',')?;
+    // Conditions and commands may follow the kind
+    let range = range.split(';').next()?;
+    let (address, length) = parse_range(range)?;
+
+    Some((kind.parse().ok()?, address, length))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(text.get(start..start + 2)?, 16).ok())
+        .collect()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Undoes the '}' escaping of binary data
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for &byte in data {
+        if escaped {
+            bytes.push(byte ^ 0x20);
+            escaped = false;
+        } else if byte == b'}' {
+            escaped = true;
+        } else {
+            bytes.push(byte);
+        }
+    }
+    bytes
+}
+
+/// write_all() for a stream that may not be ready to accept everything at once
+fn write_all<S: Write>(stream: &mut S, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(written) => data = &data[written..],
+            Err(error) if error.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+            Err(error) if error.kind() == ErrorKind::Interrupted => (),
+            Err(error) => return Err(error),
+        }
+    }
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stream replaying what the debugger sent, recording what the stub answers
+    #[derive(Default)]
+    struct MockStream {
+        input: Vec<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.input.is_empty() {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let length = buffer.len().min(self.input.len());
+            buffer[..length].copy_from_slice(&self.input[..length]);
+            self.input.drain(..length);
+            Ok(length)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(text: &str) -> String {
+        format!("${}#{:02x}", text, checksum_of(text.as_bytes()))
+    }
+
+    /// Sends packets to the stub, returning what it answered
+    fn exchange(stub: &mut GdbStub<MockStream>, chip8: &mut Chip8, packets: &[&str]) -> String {
+        for text in packets {
+            stub.stream.input.extend_from_slice(packet(text).as_bytes());
+        }
+        stub.poll(chip8).unwrap();
+        String::from_utf8(std::mem::take(&mut stub.stream.output)).unwrap()
+    }
+
+    #[test]
+    fn test_gdb_stub() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD V0, 0x12 / 0x202: LD I, 0x300 / 0x204: ADD V0, 1 / 0x206: JP 0x204
+        let program = [0x60, 0x12, 0xA3, 0x00, 0x70, 0x01, 0x12, 0x04];
+        chip8.load_program_bytes(&program).unwrap();
+        let mut stub = GdbStub::new(MockStream::default());
+
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["?"]),
+            format!("+{}", packet("S05"))
+        );
+        assert!(chip8.is_paused());
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["QStartNoAckMode"]),
+            format!("+{}", packet("OK"))
+        );
+
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["m200,4"]),
+            packet("6012a300")
+        );
+        assert_eq!(exchange(&mut stub, &mut chip8, &["s", "s"]), packet("S05"));
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["g"]),
+            packet(&format!("12{}00030402000000", "00".repeat(15)))
+        );
+        assert_eq!(exchange(&mut stub, &mut chip8, &["p11"]), packet("0402"));
+
+        assert_eq!(exchange(&mut stub, &mut chip8, &["P0=40"]), packet("OK"));
+        assert_eq!(chip8.register(0), 0x40);
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["M300,2:abcd"]),
+            packet("OK")
+        );
+        assert_eq!(&chip8.memory()[0x300..0x302], &[0xAB, 0xCD]);
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["m10000,1"]),
+            packet("E0e")
+        );
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["Mffffffffffffffff,1:00"]),
+            packet("E0e")
+        );
+        assert_eq!(
+            exchange(&mut stub, &mut chip8, &["Z2,ffffffffffffffff,2"]),
+            packet("E16")
+        );
+
+        assert_eq!(exchange(&mut stub, &mut chip8, &["Z0,206,2"]), packet("OK"));
+        assert_eq!(exchange(&mut stub, &mut chip8, &["c"]), "");
+        chip8.run_frame().unwrap();
+        assert_eq!(exchange(&mut stub, &mut chip8, &[]), packet("S05"));
+        assert_eq!(chip8.pc(), 0x206);
+        assert_eq!(chip8.register(0), 0x41);
+
+        assert_eq!(exchange(&mut stub, &mut chip8, &["z0,206,2"]), packet("OK"));
+        assert_eq!(exchange(&mut stub, &mut chip8, &["vCont;c"]), "");
+        chip8.run_frame().unwrap();
+        assert!(!chip8.is_paused());
+        stub.stream.input.push(3);
+        assert_eq!(exchange(&mut stub, &mut chip8, &[]), packet("S05"));
+        assert!(chip8.is_paused());
+
+        assert_eq!(exchange(&mut stub, &mut chip8, &["D"]), packet("OK"));
+        assert_eq!(stub.poll(&mut chip8).unwrap(), Session::Detached);
+        assert!(!chip8.is_paused());
+    }
+
+    #[test]
+    fn test_target_description() {
+        let mut chip8 = Chip8::new();
+        let mut stub = GdbStub::new(MockStream::default());
+        let description = target_description();
+
+        let reply = exchange(
+            &mut stub,
+            &mut chip8,
+            &["qXfer:features:read:target.xml:0,10"],
+        );
+        assert_eq!(
+            reply,
+            format!("+{}", packet(&format!("m{}", &description[..16])))
+        );
+        let reply = exchange(
+            &mut stub,
+            &mut chip8,
+            &[&format!(
+                "qXfer:features:read:target.xml:10,{:x}",
+                PACKET_SIZE
+            )],
+        );
+        assert_eq!(
+            reply,
+            format!("+{}", packet(&format!("l{}", &description[16..])))
+        );
+        assert!(description.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    }
+}
@@ -0,0 +1,13 @@
+pub mod assembler;
+pub mod chip8;
+pub mod console;
+pub mod disassembler;
+pub mod gdb;
+pub mod number;
+pub mod octo;
+pub mod palette;
+pub mod recording;
+pub mod screenshot;
+pub mod sound;
+
+pub use chip8::{Chip8, EmulationError, LoadError};
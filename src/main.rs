@@ -1,20 +1,150 @@
-mod chip8;
-use chip8::Chip8;
+use chip8_emulator::chip8::{Platform, SymbolTable, FRAME_RATE};
+use chip8_emulator::console::{self, DebugConsole};
+use chip8_emulator::gdb::{GdbStub, Session};
+use chip8_emulator::number::parse_number;
+use chip8_emulator::palette::Palette;
+use chip8_emulator::recording::{Recording, RecordingFormat};
+use chip8_emulator::screenshot::Screenshot;
+use chip8_emulator::sound::{self, Beeper};
+use chip8_emulator::Chip8;
 
-use sdl2;
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::event::*;
 use sdl2::keyboard::*;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 
+use std::env;
 use std::error::Error;
-use std::thread::sleep;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::process;
+use std::sync::mpsc;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 
 use std::collections::HashMap;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut chip8 = Chip8::new();
-    chip8.load_program("roms/pong.rom")?;
+const USAGE: &str = "Usage: chip8_emulator [OPTIONS] <ROM>
+
+Options:
+    --platform <NAME>    Platform and quirks preset (default: legacy), see --list-presets
+    --ipf <N>            CPU speed, in instructions per 60 Hz frame
+    --load-address <A>   Address where the ROM is loaded and starts (default: 0x200)
+    --scale <N>          Size of each CHIP-8 pixel on screen (default: 10)
+    --palette <COLORS>   Palette preset or comma separated RRGGBB colours (default: classic)
+    --tone <HZ>          Pitch of the sound timer beep (default: 440)
+    --volume <PERCENT>   Volume of the beep, 0 mutes it (default: 25)
+    --paused             Start paused, press P to resume
+    --break <ADDR>       Pause once pc reaches ADDR, can be repeated
+    --debug              Read debugger commands from stdin, see 'help' once started
+    --symbols <FILE>     Show the labels of a symbol file, --break can then take labels
+    --record <FILE>      Record the display from the start, see also F9
+    --record-format <F>  gif, or raw RGB frames for ffmpeg (default: gif)
+    --gdb <ADDR>         Wait for a GDB remote protocol debugger on ADDR, e.g. localhost:1234
+    --list-presets       List the available platform and palette presets
+    -h, --help           Print this message
+
+Hotkeys:
+    P                    Pause / resume
+    F11                  Step into (execute one instruction)
+    F10                  Step over subroutine calls
+    Shift+F11            Step out of the current subroutine
+    F1-F8                Load the state saved on slot 1-8
+    Shift+F1-F8          Save the state to slot 1-8, next to the ROM as <ROM>.state<N>
+    F9                   Start / stop recording a GIF next to the ROM, as <ROM>.<N>.gif
+    F12                  Save a screenshot next to the ROM, as <ROM>.<N>.png
+    Backspace (hold)     Rewind, up to the last 10 seconds
+    Esc                  Quit";
+
+const SAVE_SLOT_KEYS: [Keycode; 8] = [
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+];
+
+const DEFAULT_SCALE: u32 = 10;
+
+const REWIND_SECONDS: u32 = 10;
+
+const AUDIO_SAMPLE_RATE: i32 = 44100;
+
+struct Options {
+    rom: String,
+    platform: Platform,
+    instructions_per_frame: Option<usize>,
+    load_address: Option<usize>,
+    scale: u32,
+    palette: Palette,
+    tone: f64,
+    volume: f32,
+    paused: bool,
+    breakpoints: Vec<String>,
+    debug: bool,
+    symbols: Option<String>,
+    record: Option<String>,
+    record_format: RecordingFormat,
+    gdb: Option<String>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(options) {
+        eprintln!("chip8_emulator: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    let mut chip8 = Chip8::with_platform(options.platform);
+    if let Some(instructions) = options.instructions_per_frame {
+        chip8.set_instructions_per_frame(instructions);
+    }
+    if let Some(address) = options.load_address {
+        chip8.set_load_address(address);
+    }
+    chip8
+        .load_program(&options.rom)
+        .map_err(|error| format!("can't load ROM '{}': {}", options.rom, error))?;
+
+    if let Some(path) = &options.symbols {
+        chip8.debugger_mut().set_symbols(SymbolTable::load(path)?);
+    }
+    for address in &options.breakpoints {
+        let address = chip8.debugger().symbols().resolve(address)?;
+        chip8.debugger_mut().add_breakpoint(address);
+    }
+    if options.paused {
+        chip8.pause();
+    }
+
+    let mut console = DebugConsole::new();
+    let commands = if options.debug {
+        Some(spawn_command_reader())
+    } else {
+        None
+    };
+
+    let mut gdb = match &options.gdb {
+        Some(address) => {
+            println!("Waiting for a debugger on {}", address);
+            Some(GdbStub::listen(address.as_str())?)
+        }
+        None => None,
+    };
 
     let key_map: HashMap<Keycode, u8> = [
         (Keycode::Num1, 1),
@@ -42,39 +172,120 @@ fn main() -> Result<(), Box<dyn Error>> {
     let video_subsystem = sdl_context.video()?;
 
     let window = video_subsystem
-        .window("Chip8 - Emulator", 640, 320)
+        .window("Chip8 - Emulator", 64 * options.scale, 32 * options.scale)
         .position_centered()
         .build()?;
 
-    let mut canvas = window.into_canvas().present_vsync().build()?;
+    let mut canvas = window.into_canvas().build()?;
     let mut event_pump = sdl_context.event_pump()?;
 
+    // The emulator stays usable without sound, e.g. on machines without an audio device
+    let desired_spec = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE),
+        channels: Some(1),
+        samples: None,
+    };
+    let audio = sdl_context.audio().and_then(|audio_subsystem| {
+        audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            let mut beeper = Beeper::new(spec.freq as u32);
+            beeper.set_frequency(options.tone);
+            beeper.set_volume(options.volume);
+            BeeperCallback(beeper)
+        })
+    });
+    let mut audio = match audio {
+        Ok(device) => {
+            device.resume();
+            Some(device)
+        }
+        Err(error) => {
+            eprintln!("Audio disabled: {}", error);
+            None
+        }
+    };
+
+    let colors: Vec<Color> = options
+        .palette
+        .colors
+        .iter()
+        .map(|&(r, g, b)| Color::RGB(r, g, b))
+        .collect();
+    chip8.enable_rewind((REWIND_SECONDS * FRAME_RATE) as usize);
+
+    let mut recording = match &options.record {
+        Some(path) => Some(
+            Recording::create(
+                path,
+                options.record_format,
+                &chip8,
+                &options.palette,
+                options.scale as usize,
+            )
+            .map_err(|error| format!("can't create recording '{}': {}", path, error))?,
+        ),
+        None => None,
+    };
+
+    let frame_duration = Duration::from_secs(1) / FRAME_RATE;
+    let mut next_frame = Instant::now();
+
     'running: loop {
-        chip8.emulate_cycle();
+        let rewinding = event_pump
+            .keyboard_state()
+            .is_scancode_pressed(Scancode::Backspace);
+
+        if rewinding {
+            chip8.rewind(1);
+        } else {
+            // Errors pause the debugger, they are reported with the other stops below
+            let _ = chip8.run_frame();
+        }
+
+        if let Some(device) = &mut audio {
+            device.lock().0.update(&chip8);
+        }
+        if let Some(recording) = &mut recording {
+            recording.record_frame(&chip8);
+        }
+
+        if let Some(commands) = &commands {
+            for line in commands.try_iter() {
+                let output = console.execute(&mut chip8, &line);
+                if !output.is_empty() {
+                    println!("{}", output);
+                }
+            }
+        }
+        if let Some(reason) = chip8.debugger_mut().take_stop_reason() {
+            println!("{}", console::stop_message(&chip8, reason));
+        }
+        if let Some(stub) = &mut gdb {
+            match stub.poll(&mut chip8)? {
+                Session::Attached => (),
+                Session::Detached => {
+                    gdb = None;
+                    println!("Debugger detached");
+                }
+                Session::Killed => break 'running,
+            }
+        }
+
+        // Drawing in CHIP-8 pixels, SDL scales them up to the window size
+        let (width, height) = chip8.display_size();
+        canvas.set_logical_size(width as u32, height as u32)?;
+        canvas.set_draw_color(colors[0]);
         canvas.clear();
 
-        if chip8.draw_flag {
-            chip8
-                .graphics
-                .display
-                .iter()
-                .enumerate()
-                .for_each(|(index, &byte)| {
-                    let color = match byte {
-                        1 => Color::RGB(255, 255, 255),
-                        0 => Color::RGB(0, 0, 0),
-                        _ => panic!("Unknown byte value on display"),
-                    };
-                    canvas.set_draw_color(color);
-                    canvas
-                        .fill_rect(Rect::new(
-                            (index as i32 % 64) * 10,
-                            (index as i32 / 64) * 10,
-                            10,
-                            10,
-                        ))
-                        .expect("Fail drawing");
-                });
+        if chip8.draw_flag() {
+            for (index, &pixel) in chip8.display().iter().enumerate() {
+                canvas.set_draw_color(colors[(pixel as usize).min(3)]);
+                canvas.fill_rect(Rect::new(
+                    (index % width) as i32,
+                    (index / width) as i32,
+                    1,
+                    1,
+                ))?;
+            }
         }
 
         canvas.present();
@@ -84,27 +295,269 @@ fn main() -> Result<(), Box<dyn Error>> {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => {
+                    if chip8.is_paused() {
+                        chip8.resume();
+                    } else {
+                        chip8.pause();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => {
+                    // Like for run_frame(), errors are reported as debugger stops
+                    let _ = chip8.step_over();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    keymod,
+                    ..
+                } => {
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        if !chip8.step_out() {
+                            println!("Not inside a subroutine");
+                        }
+                    } else {
+                        let _ = chip8.step_into();
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => match recording.take() {
+                    Some(finished) => match finished.finish() {
+                        Ok(()) => println!("Recording stopped"),
+                        Err(error) => eprintln!("Can't write the recording: {}", error),
+                    },
+                    None => {
+                        let path = numbered_path(&options.rom, "gif");
+                        match Recording::create(
+                            &path,
+                            RecordingFormat::Gif,
+                            &chip8,
+                            &options.palette,
+                            options.scale as usize,
+                        ) {
+                            Ok(started) => {
+                                recording = Some(started);
+                                println!("Recording to {}", path);
+                            }
+                            Err(error) => eprintln!("Can't create {}: {}", path, error),
+                        }
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => {
+                    let path = numbered_path(&options.rom, "png");
+                    let screenshot =
+                        Screenshot::capture(&chip8, &options.palette, options.scale as usize);
+                    match screenshot.save_png(&path) {
+                        Ok(()) => println!("Saved screenshot to {}", path),
+                        Err(error) => eprintln!("Can't write {}: {}", path, error),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if SAVE_SLOT_KEYS.contains(&keycode) => {
+                    let slot = SAVE_SLOT_KEYS
+                        .iter()
+                        .position(|&key| key == keycode)
+                        .unwrap()
+                        + 1;
+                    let path = state_path(&options.rom, slot);
+
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        match fs::write(&path, chip8.save_state()) {
+                            Ok(()) => println!("Saved state to slot {}", slot),
+                            Err(error) => eprintln!("Can't write {}: {}", path, error),
+                        }
+                    } else {
+                        match fs::read(&path) {
+                            Ok(state) => match chip8.load_state(&state) {
+                                Ok(()) => println!("Loaded state from slot {}", slot),
+                                Err(error) => eprintln!("Can't load {}: {}", path, error),
+                            },
+                            Err(error) => eprintln!("Can't read {}: {}", path, error),
+                        }
+                    }
+                }
                 Event::Quit { .. } => break 'running,
                 _ => (),
             }
         }
 
-        event_pump
-            .keyboard_state()
-            .scancodes()
-            .for_each(|(scancode, is_pressed)| {
-                if let Some(keycode) = Keycode::from_scancode(scancode) {
-                    if key_map.contains_key(&keycode) {
-                        let key_as_u8 = key_map[&keycode];
-
-                        if is_pressed {
-                            chip8.press_key(key_as_u8)
-                        } else {
-                            chip8.release_key(key_as_u8)
-                        }
+        for (scancode, is_pressed) in event_pump.keyboard_state().scancodes() {
+            if let Some(keycode) = Keycode::from_scancode(scancode) {
+                if let Some(&key_as_u8) = key_map.get(&keycode) {
+                    if is_pressed {
+                        chip8.press_key(key_as_u8)?
+                    } else {
+                        chip8.release_key(key_as_u8)?
                     }
                 }
-            });
+            }
+        }
+
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+
+    if let Some(recording) = recording {
+        recording
+            .finish()
+            .map_err(|error| format!("can't write the recording: {}", error))?;
     }
     Ok(())
 }
+
+/// Plays the Beeper samples from the SDL audio thread
+struct BeeperCallback(Beeper);
+
+impl AudioCallback for BeeperCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        self.0.fill(samples);
+    }
+}
+
+/// Reads debugger commands from stdin on a separate thread, so the window keeps running
+fn spawn_command_reader() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let sent = line.map(|line| sender.send(line).is_ok());
+            if sent.ok() != Some(true) {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// File holding the save state of slot, next to the ROM
+fn state_path(rom: &str, slot: usize) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+/// First unused <ROM>.<N>.<extension> file next to the ROM, for screenshots and recordings
+fn numbered_path(rom: &str, extension: &str) -> String {
+    (1..)
+        .map(|number| format!("{}.{}.{}", rom, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+fn list_presets() {
+    println!("Platforms:");
+    for platform in Platform::ALL.iter() {
+        println!("    {:<10}{}", platform.name(), platform.description());
+    }
+
+    println!("\nPalettes:");
+    for (name, palette) in Palette::PRESETS.iter() {
+        let colors: Vec<String> = palette
+            .colors
+            .iter()
+            .map(|(r, g, b)| format!("{:02x}{:02x}{:02x}", r, g, b))
+            .collect();
+        println!("    {:<10}{}", name, colors.join(","));
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        platform: Platform::default(),
+        instructions_per_frame: None,
+        load_address: None,
+        scale: DEFAULT_SCALE,
+        palette: Palette::default(),
+        tone: sound::DEFAULT_FREQUENCY,
+        volume: sound::DEFAULT_VOLUME,
+        paused: false,
+        breakpoints: Vec::new(),
+        debug: false,
+        symbols: None,
+        record: None,
+        record_format: RecordingFormat::default(),
+        gdb: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--platform" => options.platform = value()?.parse()?,
+            "--ipf" => options.instructions_per_frame = Some(parse_number(&value()?)?),
+            "--load-address" => {
+                options.load_address = Some(parse_number(&value()?)?);
+            }
+            "--scale" => {
+                let text = value()?;
+                options.scale = match text.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("invalid scale '{}'", text)),
+                };
+            }
+            "--palette" => options.palette = value()?.parse()?,
+            "--tone" => {
+                let text = value()?;
+                options.tone = match text.parse() {
+                    Ok(tone) if tone > 0.0 => tone,
+                    _ => return Err(format!("invalid tone '{}'", text)),
+                };
+            }
+            "--volume" => {
+                let text = value()?;
+                options.volume = match text.parse::<u8>() {
+                    Ok(volume) if volume <= 100 => volume as f32 / 100.0,
+                    _ => return Err(format!("invalid volume '{}'", text)),
+                };
+            }
+            "--paused" => options.paused = true,
+            "--break" => options.breakpoints.push(value()?),
+            "--debug" => options.debug = true,
+            "--symbols" => options.symbols = Some(value()?),
+            "--record" => options.record = Some(value()?),
+            "--record-format" => options.record_format = value()?.parse()?,
+            "--gdb" => options.gdb = Some(value()?),
+            "--list-presets" => {
+                list_presets();
+                process::exit(0);
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or("missing ROM path")?;
+    Ok(options)
+}
@@ -0,0 +1,9 @@
+/// Parses a decimal number, or an hexadecimal one prefixed by 0x
+pub fn parse_number(text: &str) -> Result<usize, String> {
+    let result = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+
+    result.map_err(|_| format!("invalid number '{}'", text))
+}
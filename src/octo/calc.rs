@@ -0,0 +1,117 @@
+use super::lexer::{self, Token};
+
+use std::convert::TryFrom;
+
+const BINARY_OPERATORS: [&str; 17] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "<", ">", "<=", ">=", "==", "!=", "pow",
+];
+
+const UNARY_OPERATORS: [&str; 9] = ["-", "~", "!", "abs", "sqrt", "sin", "cos", "floor", "ceil"];
+
+/// Evaluates a :calc expression. Like in Octo, operators have no precedence
+/// and are applied right to left, so 2 * 3 + 1 is 8
+pub fn evaluate<F>(tokens: &[Token], lookup: &F) -> Result<f64, String>
+where
+    F: Fn(&str) -> Option<f64>,
+{
+    let mut position = 0;
+    let value = expression(tokens, &mut position, lookup)?;
+
+    match tokens.get(position) {
+        Some(token) => Err(format!("unexpected '{}' in expression", token.text)),
+        None => Ok(value),
+    }
+}
+
+fn expression<F>(tokens: &[Token], position: &mut usize, lookup: &F) -> Result<f64, String>
+where
+    F: Fn(&str) -> Option<f64>,
+{
+    let left = term(tokens, position, lookup)?;
+
+    match tokens.get(*position) {
+        Some(token) if BINARY_OPERATORS.contains(&token.text.as_str()) => {
+            *position += 1;
+            let right = expression(tokens, position, lookup)?;
+            binary(&token.text, left, right)
+        }
+        _ => Ok(left),
+    }
+}
+
+fn term<F>(tokens: &[Token], position: &mut usize, lookup: &F) -> Result<f64, String>
+where
+    F: Fn(&str) -> Option<f64>,
+{
+    let token = tokens
+        .get(*position)
+        .ok_or("expression is missing a value")?;
+    *position += 1;
+
+    if token.text == "(" {
+        let value = expression(tokens, position, lookup)?;
+        return match tokens.get(*position) {
+            Some(token) if token.text == ")" => {
+                *position += 1;
+                Ok(value)
+            }
+            _ => Err("missing ')' in expression".to_string()),
+        };
+    }
+    if UNARY_OPERATORS.contains(&token.text.as_str()) {
+        let value = term(tokens, position, lookup)?;
+        return Ok(unary(&token.text, value));
+    }
+
+    lexer::parse_number(&token.text)
+        .map(|value| value as f64)
+        .or_else(|| lookup(&token.text))
+        .ok_or_else(|| format!("undefined name '{}' in expression", token.text))
+}
+
+fn binary(operator: &str, left: f64, right: f64) -> Result<f64, String> {
+    let (l, r) = (left as i64, right as i64);
+    let boolean = |condition: bool| if condition { 1.0 } else { 0.0 };
+    let shift = |shifted: Option<i64>| {
+        shifted
+            .map(|value| value as f64)
+            .ok_or_else(|| format!("invalid shift {} {} {}", l, operator, r))
+    };
+
+    let value = match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "&" => (l & r) as f64,
+        "|" => (l | r) as f64,
+        "^" => (l ^ r) as f64,
+        "<<" => return shift(u32::try_from(r).ok().and_then(|r| l.checked_shl(r))),
+        ">>" => return shift(u32::try_from(r).ok().and_then(|r| l.checked_shr(r))),
+        "<" => boolean(left < right),
+        ">" => boolean(left > right),
+        "<=" => boolean(left <= right),
+        ">=" => boolean(left >= right),
+        "==" => boolean(left == right),
+        "!=" => boolean(left != right),
+        "pow" => left.powf(right),
+        _ => unreachable!("unknown operator {}", operator),
+    };
+    Ok(value)
+}
+
+fn unary(operator: &str, value: f64) -> f64 {
+    match operator {
+        "-" => -value,
+        "~" => !(value as i64) as f64,
+        "!" => (value == 0.0) as i64 as f64,
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "floor" => value.floor(),
+        "ceil" => value.ceil(),
+        _ => unreachable!("unknown operator {}", operator),
+    }
+}
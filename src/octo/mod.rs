@@ -0,0 +1,872 @@
+use crate::assembler::{Assembly, AssemblyError};
+use crate::chip8::PROGRAM_START;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+mod calc;
+
+mod lexer;
+use lexer::Token;
+
+/// Highest address reachable by i := long
+const MAX_ADDRESS: usize = 0xFFFF;
+
+/// Macro expansions allowed per program, guarding against recursive macros
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Compiles Octo source into a ROM loaded at 0x200, along with its labels
+pub fn compile(source: &str) -> Result<Assembly, AssemblyError> {
+    Compiler::new(lexer::tokenize(source))
+        .compile()
+        .map_err(|(line, message)| AssemblyError {
+            file: None,
+            line,
+            message,
+        })
+}
+
+/// Compiles the Octo source file at path
+pub fn compile_file<P: AsRef<Path>>(path: P) -> Result<Assembly, AssemblyError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|error| AssemblyError {
+        file: Some(path.to_path_buf()),
+        line: 0,
+        message: format!("can't read {}: {}", path.display(), error),
+    })?;
+
+    compile(&source).map_err(|error| AssemblyError {
+        file: Some(path.to_path_buf()),
+        ..error
+    })
+}
+
+/// Error message along with its line
+type CompileError = (usize, String);
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Address operand that can't be resolved until its label is defined
+struct Fixup {
+    address: usize,
+    label: String,
+    long: bool,
+    line: usize,
+}
+
+/// Jump left to patch with the address a block ends at
+struct Block {
+    kind: BlockKind,
+    /// Start of the loop for again
+    start: usize,
+    /// Jumps out of the block, to the else, the end or after the loop
+    jumps: Vec<usize>,
+    line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    If,
+    Else,
+    Loop,
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    /// Memory from PROGRAM_START
+    rom: Vec<u8>,
+    here: usize,
+    /// Whether 0x200 holds the jump to main
+    jump_to_main: bool,
+    labels: BTreeMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>) -> Self {
+        Compiler {
+            tokens: tokens.into(),
+            line: 0,
+            rom: vec![0x10, 0x00],
+            here: PROGRAM_START + 2,
+            jump_to_main: true,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> Result<Assembly, CompileError> {
+        while !self.tokens.is_empty() {
+            self.statement().map_err(|message| (self.line, message))?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let message = match block.kind {
+                BlockKind::Loop => "loop without again",
+                _ => "begin without end",
+            };
+            return Err((block.line, message.to_string()));
+        }
+
+        let main = *self
+            .labels
+            .get("main")
+            .ok_or((0, "this program is missing a 'main' label".to_string()))?;
+        if self.jump_to_main {
+            self.write_opcode(PROGRAM_START, 0x1000 | main as u16);
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or_else(|| (fixup.line, format!("undefined label '{}'", fixup.label)))?;
+
+            if fixup.long {
+                self.write_opcode(fixup.address + 2, address as u16);
+            } else if address > 0xFFF {
+                let message = format!("label '{}' is past 0xFFF, use i := long", fixup.label);
+                return Err((fixup.line, message));
+            } else {
+                let opcode = self.read_opcode(fixup.address) | address as u16;
+                self.write_opcode(fixup.address, opcode);
+            }
+        }
+
+        if self.rom.is_empty() {
+            return Err((0, "no code or data to compile".to_string()));
+        }
+        Ok(Assembly {
+            rom: self.rom,
+            origin: PROGRAM_START,
+            labels: self.labels,
+        })
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.pop_front().ok_or("unexpected end of program")?;
+        self.line = token.line;
+
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected '{}', got '{}'", expected, token)),
+        }
+    }
+
+    /// Tokens up to the matching closing brace, after an opening one
+    fn braces(&mut self) -> Result<Vec<Token>, String> {
+        self.expect("{")?;
+
+        let mut depth = 1;
+        let mut tokens = Vec::new();
+        loop {
+            let line = self.line;
+            let text = self
+                .next()
+                .map_err(|_| format!("'{{' on line {} is never closed", line))?;
+            match text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 1 => return Ok(tokens),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            tokens.push(Token {
+                text,
+                line: self.line,
+            });
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+
+        if let Some(x) = self.register(&token) {
+            return self.register_assignment(x);
+        }
+        if let Some(value) = lexer::parse_number(&token) {
+            return self.emit_byte(value);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(name)?;
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.next()?;
+                let x = self
+                    .register(&register)
+                    .ok_or_else(|| format!("'{}' is not a register", register))?;
+                self.aliases.insert(name, x as u8);
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.define_constant(name, value as f64)?;
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let expression = self.braces()?;
+                let value = calc::evaluate(&expression, &|name: &str| self.lookup(name))?;
+                self.define_constant(name, value)?;
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut parameters = Vec::new();
+                while self.peek().is_some_and(|token| token != "{") {
+                    parameters.push(self.next()?);
+                }
+                let body = self.braces()?;
+                self.macros.insert(name, Macro { parameters, body });
+            }
+            ":org" => {
+                let address = self.next()?;
+                let address = self.value(&address)?;
+                if address < PROGRAM_START as i64 || address > MAX_ADDRESS as i64 {
+                    return Err(format!("can't :org at {:#x}", address));
+                }
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let value = match self.peek() {
+                    Some("{") => {
+                        let expression = self.braces()?;
+                        calc::evaluate(&expression, &|name: &str| self.lookup(name))? as i64
+                    }
+                    _ => {
+                        let value = self.next()?;
+                        self.value(&value)?
+                    }
+                };
+                self.emit_byte(value)?;
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_address(0x2000, &target)?;
+            }
+            "clear" => self.emit(0x00E0)?,
+            "return" | ";" => self.emit(0x00EE)?,
+            "exit" => self.emit(0x00FD)?,
+            "lores" => self.emit(0x00FE)?,
+            "hires" => self.emit(0x00FF)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)?;
+            }
+            "scroll-right" => self.emit(0x00FB)?,
+            "scroll-left" => self.emit(0x00FC)?,
+            "jump" => {
+                let target = self.next()?;
+                self.emit_address(0x1000, &target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_address(0xB000, &target)?;
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.emit(0xF033 | x << 8)?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                let store = token == "save";
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.expect_register()?;
+                    let opcode = if store { 0x5002 } else { 0x5003 };
+                    self.emit(opcode | x << 8 | y << 4)?;
+                } else {
+                    let opcode = if store { 0xF055 } else { 0xF065 };
+                    self.emit(opcode | x << 8)?;
+                }
+            }
+            "saveflags" => {
+                let x = self.expect_register()?;
+                self.emit(0xF075 | x << 8)?;
+            }
+            "loadflags" => {
+                let x = self.expect_register()?;
+                self.emit(0xF085 | x << 8)?;
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)?;
+            }
+            "audio" => self.emit(0xF002)?,
+            "i" => self.index_assignment()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                let opcode = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(opcode | x << 8)?;
+            }
+            "if" => self.conditional()?,
+            "else" => {
+                let block = self.blocks.pop();
+                match block {
+                    Some(block) if block.kind == BlockKind::If => {
+                        let jump = self.here;
+                        self.emit(0x1000)?;
+                        self.patch_jumps(&block.jumps, self.here);
+                        self.blocks.push(Block {
+                            kind: BlockKind::Else,
+                            jumps: vec![jump],
+                            ..block
+                        });
+                    }
+                    _ => return Err("else without if ... begin".to_string()),
+                }
+            }
+            "end" => match self.blocks.pop() {
+                Some(block) if block.kind != BlockKind::Loop => {
+                    self.patch_jumps(&block.jumps, self.here)
+                }
+                _ => return Err("end without if ... begin".to_string()),
+            },
+            "loop" => self.blocks.push(Block {
+                kind: BlockKind::Loop,
+                start: self.here,
+                jumps: Vec::new(),
+                line: self.line,
+            }),
+            "while" => {
+                let loop_index = self
+                    .blocks
+                    .iter()
+                    .rposition(|block| block.kind == BlockKind::Loop)
+                    .ok_or("while outside of a loop")?;
+                let skip = self.condition()?;
+                self.emit_condition(skip, true)?;
+                self.blocks[loop_index].jumps.push(self.here);
+                self.emit(0x1000)?;
+            }
+            "again" => match self.blocks.pop() {
+                Some(block) if block.kind == BlockKind::Loop => {
+                    self.emit(0x1000 | block.start as u16)?;
+                    self.patch_jumps(&block.jumps, self.here);
+                }
+                _ => return Err("again without loop".to_string()),
+            },
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            _ if self.constants.contains_key(&token) => {
+                let value = self.constants[&token] as i64;
+                self.emit_byte(value)?;
+            }
+            _ if is_name(&token) => self.emit_address(0x2000, &token)?,
+            _ => return Err(format!("unexpected '{}'", token)),
+        }
+        Ok(())
+    }
+
+    fn register_assignment(&mut self, x: u16) -> Result<(), String> {
+        let operator = self.next()?;
+        let operand = self.next()?;
+
+        if let Some(y) = self.register(&operand) {
+            let opcode = match operator.as_str() {
+                ":=" => 0x8000,
+                "|=" => 0x8001,
+                "&=" => 0x8002,
+                "^=" => 0x8003,
+                "+=" => 0x8004,
+                "-=" => 0x8005,
+                ">>=" => 0x8006,
+                "=-" => 0x8007,
+                "<<=" => 0x800E,
+                _ => return Err(format!("can't use '{}' between registers", operator)),
+            };
+            return self.emit(opcode | x << 8 | y << 4);
+        }
+
+        let opcode = match (operator.as_str(), operand.as_str()) {
+            (":=", "delay") => 0xF007 | x << 8,
+            (":=", "key") => 0xF00A | x << 8,
+            (":=", "random") => {
+                let mask = self.next()?;
+                0xC000 | x << 8 | self.byte(&mask)?
+            }
+            (":=", value) => 0x6000 | x << 8 | self.byte(value)?,
+            ("+=", value) => 0x7000 | x << 8 | self.byte(value)?,
+            ("-=", value) => {
+                let value = self.value(value)?;
+                0x7000 | x << 8 | to_byte(value.wrapping_neg())?
+            }
+            _ => return Err(format!("can't use '{}' with '{}'", operator, operand)),
+        };
+        self.emit(opcode)
+    }
+
+    fn index_assignment(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        let operand = self.next()?;
+
+        match (operator.as_str(), operand.as_str()) {
+            (":=", "long") => {
+                let target = self.next()?;
+                self.emit_long(&target)
+            }
+            (":=", "hex") => {
+                let x = self.expect_register()?;
+                self.emit(0xF029 | x << 8)
+            }
+            (":=", "bighex") => {
+                let x = self.expect_register()?;
+                self.emit(0xF030 | x << 8)
+            }
+            (":=", target) => self.emit_address(0xA000, target),
+            ("+=", register) => {
+                let x = self
+                    .register(register)
+                    .ok_or_else(|| format!("'{}' is not a register", register))?;
+                self.emit(0xF01E | x << 8)
+            }
+            _ => Err(format!("can't use '{}' with i", operator)),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<(), String> {
+        let skip = self.condition()?;
+
+        match self.next()?.as_str() {
+            "then" => self.emit_condition(skip, false),
+            "begin" => {
+                self.emit_condition(skip, true)?;
+                self.blocks.push(Block {
+                    kind: BlockKind::If,
+                    start: self.here,
+                    jumps: vec![self.here],
+                    line: self.line,
+                });
+                self.emit(0x1000)
+            }
+            token => Err(format!("expected 'then' or 'begin', got '{}'", token)),
+        }
+    }
+
+    /// Opcodes of a condition, the last one skipping the next instruction
+    /// when the condition is false
+    fn condition(&mut self) -> Result<Vec<u16>, String> {
+        let x = self.expect_register()?;
+        let operator = self.next()?;
+
+        match operator.as_str() {
+            "key" => return Ok(vec![0xE0A1 | x << 8]),
+            "-key" => return Ok(vec![0xE09E | x << 8]),
+            _ => (),
+        }
+
+        let operand = self.next()?;
+        let y = self.register(&operand);
+        let opcodes = match (operator.as_str(), y) {
+            ("==", Some(y)) => vec![0x9000 | x << 8 | y << 4],
+            ("!=", Some(y)) => vec![0x5000 | x << 8 | y << 4],
+            ("==", None) => vec![0x4000 | x << 8 | self.byte(&operand)?],
+            ("!=", None) => vec![0x3000 | x << 8 | self.byte(&operand)?],
+            ("<", _) | (">", _) | ("<=", _) | (">=", _) => {
+                // VF ends up 1 when x >= operand for < and >=, when x <= operand for > and <=
+                let lower = operator == "<" || operator == ">=";
+                let mut opcodes = match (y, lower) {
+                    (Some(y), true) => vec![0x8F00 | x << 4, 0x8F05 | y << 4],
+                    (Some(y), false) => vec![0x8F00 | y << 4, 0x8F05 | x << 4],
+                    (None, true) => vec![0x6F00 | self.byte(&operand)?, 0x8F07 | x << 4],
+                    (None, false) => vec![0x6F00 | self.byte(&operand)?, 0x8F05 | x << 4],
+                };
+                let strict = operator == "<" || operator == ">";
+                opcodes.push(if strict { 0x3F01 } else { 0x4F01 });
+                opcodes
+            }
+            _ => return Err(format!("unknown comparison '{}'", operator)),
+        };
+        Ok(opcodes)
+    }
+
+    /// Emits a condition, inverting its skip when jumping over a block
+    fn emit_condition(&mut self, mut opcodes: Vec<u16>, invert: bool) -> Result<(), String> {
+        if invert {
+            let skip = opcodes.last_mut().unwrap();
+            *skip = match *skip >> 12 {
+                0x3 => *skip + 0x1000,
+                0x4 => *skip - 0x1000,
+                0x5 => *skip + 0x4000,
+                0x9 => *skip - 0x4000,
+                _ if *skip & 0xFF == 0x9E => *skip + 0x3,
+                _ => *skip - 0x3,
+            };
+        }
+
+        for opcode in opcodes {
+            self.emit(opcode)?;
+        }
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("too many expansions of macro '{}'", name));
+        }
+
+        let parameter_count = self.macros[name].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..parameter_count {
+            let argument = self.next()?;
+            arguments.insert(self.macros[name].parameters[index].clone(), argument);
+        }
+
+        let line = self.line;
+        for token in self.macros[name].body.iter().rev() {
+            let text = arguments.get(&token.text).unwrap_or(&token.text);
+            self.tokens.push_front(Token {
+                text: text.clone(),
+                line,
+            });
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), String> {
+        self.check_name(&name)?;
+
+        // Like Octo, drops the jump to main when main comes first
+        let nothing_before = self.labels.is_empty() && self.rom.len() == 2;
+        if name == "main" && self.jump_to_main && nothing_before && self.here == PROGRAM_START + 2 {
+            self.rom.clear();
+            self.here = PROGRAM_START;
+            self.jump_to_main = false;
+        }
+
+        self.labels.insert(name, self.here);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), String> {
+        self.check_name(&name)?;
+        self.constants.insert(name, value);
+
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), String> {
+        if !is_name(name) || self.register(name).is_some() {
+            return Err(format!("invalid name '{}'", name));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(format!("'{}' is already defined", name));
+        }
+        Ok(())
+    }
+
+    /// Value of a name in :calc expressions, HERE being the current address
+    fn lookup(&self, name: &str) -> Option<f64> {
+        match name {
+            "HERE" => Some(self.here as f64),
+            _ => self
+                .constants
+                .get(name)
+                .copied()
+                .or_else(|| self.labels.get(name).map(|&address| address as f64)),
+        }
+    }
+
+    /// Number, constant or label already defined
+    fn value(&self, token: &str) -> Result<i64, String> {
+        lexer::parse_number(token)
+            .or_else(|| self.lookup(token).map(|value| value as i64))
+            .ok_or_else(|| format!("undefined name '{}'", token))
+    }
+
+    fn byte(&self, token: &str) -> Result<u16, String> {
+        to_byte(self.value(token)?)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+
+        match self.value(&token)? {
+            value @ 0..=0xF => Ok(value as u16),
+            value => Err(format!("{} doesn't fit in a nibble", value)),
+        }
+    }
+
+    fn register(&self, token: &str) -> Option<u16> {
+        if let Some(&x) = self.aliases.get(token) {
+            return Some(x as u16);
+        }
+
+        let mut chars = token.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => {
+                x.to_digit(16).map(|x| x as u16)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u16, String> {
+        let token = self.next()?;
+
+        self.register(&token)
+            .ok_or_else(|| format!("expected a register, got '{}'", token))
+    }
+
+    fn emit_byte(&mut self, value: i64) -> Result<(), String> {
+        let byte = to_byte(value)? as u8;
+        if self.here > MAX_ADDRESS {
+            return Err("program goes past the end of memory".to_string());
+        }
+
+        let offset = self.here - PROGRAM_START;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), String> {
+        self.emit_byte((opcode >> 8) as i64)?;
+        self.emit_byte((opcode & 0xFF) as i64)
+    }
+
+    /// Emits opcode | NNN, NNN being resolved at the end for labels not defined yet
+    fn emit_address(&mut self, opcode: u16, target: &str) -> Result<(), String> {
+        let address = match self.value(target) {
+            Ok(address @ 0..=0xFFF) => address as u16,
+            Ok(address) => return Err(format!("address {:#x} is past 0xFFF", address)),
+            Err(_) if is_name(target) => {
+                self.fixups.push(Fixup {
+                    address: self.here,
+                    label: target.to_string(),
+                    long: false,
+                    line: self.line,
+                });
+                0
+            }
+            Err(error) => return Err(error),
+        };
+        self.emit(opcode | address)
+    }
+
+    fn emit_long(&mut self, target: &str) -> Result<(), String> {
+        let address = match self.value(target) {
+            Ok(address @ 0..=0xFFFF) => address as u16,
+            Ok(address) => return Err(format!("address {:#x} is past 0xFFFF", address)),
+            Err(_) if is_name(target) => {
+                self.fixups.push(Fixup {
+                    address: self.here,
+                    label: target.to_string(),
+                    long: true,
+                    line: self.line,
+                });
+                0
+            }
+            Err(error) => return Err(error),
+        };
+        self.emit(0xF000)?;
+        self.emit(address)
+    }
+
+    fn patch_jumps(&mut self, jumps: &[usize], target: usize) {
+        for &jump in jumps {
+            self.write_opcode(jump, 0x1000 | target as u16);
+        }
+    }
+
+    fn read_opcode(&self, address: usize) -> u16 {
+        let offset = address - PROGRAM_START;
+        (self.rom[offset] as u16) << 8 | self.rom[offset + 1] as u16
+    }
+
+    fn write_opcode(&mut self, address: usize, opcode: u16) {
+        let offset = address - PROGRAM_START;
+        self.rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+}
+
+/// Bytes from -128 to 255, negative ones being stored in two's complement
+fn to_byte(value: i64) -> Result<u16, String> {
+    match value {
+        -0x80..=0xFF => Ok(value as u16 & 0xFF),
+        _ => Err(format!("{} doesn't fit in a byte", value)),
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    let mut chars = token.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source).unwrap().rom
+    }
+
+    #[test]
+    fn test_compile() {
+        let source = "
+:alias x v3
+:const SPEED 2
+:calc LIMIT { 64 - 8 }  # right to left, no precedence
+:macro move reg amount { reg += amount }
+
+: main
+    x := 0
+    i := ball
+    loop
+        sprite x v4 2
+        move x SPEED
+        if x > LIMIT then x := 0
+        if v0 == v1 begin
+            clear
+        else
+            draw
+        end
+        while x != 40
+    again
+    jump main
+: draw
+    i := long ball
+    save v0 - v2 ;
+: ball
+    0xC0 0b11000000";
+        let assembly = compile(source).unwrap();
+
+        assert_eq!(
+            assembly.rom,
+            [
+                0x63, 0x00, // x := 0
+                0xA2, 0x2A, // i := ball
+                0xD3, 0x42, // sprite
+                0x73, 0x02, // move x SPEED
+                0x6F, 0x38, 0x8F, 0x35, 0x3F, 0x01, 0x63, 0x00, // if x > LIMIT then
+                0x50, 0x10, 0x12, 0x18, // if v0 == v1 begin
+                0x00, 0xE0, 0x12, 0x1A, // clear, else
+                0x22, 0x22, // draw
+                0x43, 0x28, 0x12, 0x20, // while x != 40
+                0x12, 0x04, // again
+                0x12, 0x00, // jump main
+                0xF0, 0x00, 0x02, 0x2A, 0x50, 0x22, 0x00, 0xEE, // draw
+                0xC0, 0xC0, // ball
+            ]
+        );
+        assert_eq!(assembly.labels["draw"], 0x222);
+        assert_eq!(assembly.labels["ball"], 0x22A);
+    }
+
+    #[test]
+    fn test_main_jump() {
+        assert_eq!(rom(": main jump main"), [0x12, 0x00]);
+        assert_eq!(
+            rom(": sub ; : main sub"),
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+        assert_eq!(rom(":org 0x300 : main ;")[0..2], [0x13, 0x00]);
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert_eq!(
+            rom(": main if v1 key then ; if v2 -key then ; if v1 < v2 then ;"),
+            [
+                0xE1, 0xA1, 0x00, 0xEE, 0xE2, 0x9E, 0x00, 0xEE, 0x8F, 0x10, 0x8F, 0x25, 0x3F, 0x01,
+                0x00, 0xEE
+            ]
+        );
+        assert_eq!(
+            rom(": main if v1 >= 5 begin ; end"),
+            [0x6F, 0x05, 0x8F, 0x17, 0x3F, 0x01, 0x12, 0x0A, 0x00, 0xEE]
+        );
+        assert_eq!(
+            rom(": main loop while v0 key again"),
+            [0xE0, 0x9E, 0x12, 0x06, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| {
+            let error = compile(source).unwrap_err();
+            (error.line, error.message)
+        };
+
+        assert_eq!(
+            error(": start ;"),
+            (0, "this program is missing a 'main' label".to_string())
+        );
+        assert_eq!(
+            error(": main\n\nv0 := 256"),
+            (3, "256 doesn't fit in a byte".to_string())
+        );
+        assert_eq!(
+            error(": main\njump nowhere"),
+            (2, "undefined label 'nowhere'".to_string())
+        );
+        assert_eq!(
+            error(": main\nloop\nclear"),
+            (2, "loop without again".to_string())
+        );
+        assert_eq!(
+            error(": main\n:calc X { 1 + }"),
+            (2, "expression is missing a value".to_string())
+        );
+        assert_eq!(
+            error(": main\n:calc X { 1 << 70 }"),
+            (2, "invalid shift 1 << 70".to_string())
+        );
+        assert_eq!(
+            error(": main\n:calc X { 1 >> -1 }"),
+            (2, "invalid shift 1 >> -1".to_string())
+        );
+        assert_eq!(
+            error(": main\n: main"),
+            (2, "'main' is already defined".to_string())
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::str::FromStr;
+
+/// RGB colours for each pixel value of the display
+///
+/// Index 0 is the background, 1 and 2 the XO-CHIP planes and 3 both planes at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [(u8, u8, u8); 4],
+}
+
+impl Palette {
+    /// Named presets, as accepted by from_str()
+    pub const PRESETS: [(&'static str, Palette); 4] = [
+        (
+            "classic",
+            Palette {
+                colors: [(0, 0, 0), (255, 255, 255), (170, 170, 170), (85, 85, 85)],
+            },
+        ),
+        (
+            "octo",
+            Palette {
+                colors: [(153, 102, 0), (255, 204, 0), (255, 102, 0), (102, 34, 0)],
+            },
+        ),
+        (
+            "green",
+            Palette {
+                colors: [(15, 56, 15), (155, 188, 15), (139, 172, 15), (48, 98, 48)],
+            },
+        ),
+        (
+            "amber",
+            Palette {
+                colors: [(40, 20, 0), (255, 176, 0), (204, 120, 0), (120, 64, 0)],
+            },
+        ),
+    ];
+
+    /// Colour of a display pixel value. Values above 3 use the last colour
+    pub fn color(&self, pixel: u8) -> (u8, u8, u8) {
+        self.colors[(pixel as usize).min(3)]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::PRESETS[0].1
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parses a preset name, or 2 to 4 comma separated RRGGBB colours
+    ///
+    /// Missing colours are taken from the classic palette
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if let Some((_, palette)) = Self::PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(text))
+        {
+            return Ok(*palette);
+        }
+
+        let invalid = || format!("invalid palette '{}'", text);
+        let colors: Vec<&str> = text.split(',').map(str::trim).collect();
+        if colors.len() < 2 || colors.len() > 4 {
+            return Err(invalid());
+        }
+
+        let mut palette = Palette::default();
+        for (slot, color) in palette.colors.iter_mut().zip(colors) {
+            let color = color.trim_start_matches('#');
+            let value = u32::from_str_radix(color, 16).map_err(|_| invalid())?;
+            if color.len() != 6 {
+                return Err(invalid());
+            }
+
+            *slot = ((value >> 16) as u8, (value >> 8) as u8, value as u8);
+        }
+
+        Ok(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_palette() {
+        assert_eq!("classic".parse(), Ok(Palette::default()));
+        assert_eq!("OCTO".parse::<Palette>().unwrap().color(1), (255, 204, 0));
+
+        let palette: Palette = "#102030, 405060".parse().unwrap();
+        assert_eq!(palette.color(0), (0x10, 0x20, 0x30));
+        assert_eq!(palette.color(1), (0x40, 0x50, 0x60));
+        assert_eq!(palette.color(3), Palette::default().color(3));
+
+        assert!("102030".parse::<Palette>().is_err());
+        assert!("10203,405060".parse::<Palette>().is_err());
+        assert!("sepia".parse::<Palette>().is_err());
+    }
+}
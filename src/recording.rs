@@ -0,0 +1,427 @@
+use crate::chip8::FRAME_RATE;
+use crate::palette::Palette;
+use crate::screenshot::{BitWriter, Screenshot};
+use crate::Chip8;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Largest LZW code, GIF codes being at most 12 bits long
+const MAX_LZW_CODE: u16 = 4096;
+
+/// Bits per pixel of the GIF colour table and LZW codes, for the 4 display values
+const GIF_COLOR_BITS: u8 = 2;
+
+/// How a Recording stores the frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingFormat {
+    /// Animated GIF, consecutive identical frames being merged into one
+    #[default]
+    Gif,
+    /// Every frame as raw 24-bit RGB, for instance to convert with ffmpeg:
+    /// ffmpeg -f rawvideo -pixel_format rgb24 -video_size WxH -framerate 60 -i FILE out.mp4
+    Raw,
+}
+
+impl FromStr for RecordingFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "gif" => Ok(RecordingFormat::Gif),
+            "raw" => Ok(RecordingFormat::Raw),
+            _ => Err(format!(
+                "unknown recording format '{}', expected gif or raw",
+                name
+            )),
+        }
+    }
+}
+
+/// Records the display of a Chip8 once per frame, keeping the 60 Hz timing
+///
+/// The size of the frames is fixed when the recording starts. Platforms with a hires mode
+/// are recorded at 128x64 times the scale, so lores frames are doubled instead of the hires
+/// ones being cut down
+pub struct Recording {
+    output: Box<dyn Write>,
+    format: RecordingFormat,
+    palette: Palette,
+    width: usize,
+    height: usize,
+    frames: u64,
+    /// GIF frame not written yet, since its duration grows while the display is the same
+    pending: Option<Screenshot>,
+    /// Frame at which the pending one started
+    pending_start: u64,
+    /// First write error, reported by finish()
+    error: Option<io::Error>,
+}
+
+impl Recording {
+    pub fn new<W: Write + 'static>(
+        output: W,
+        format: RecordingFormat,
+        chip8: &Chip8,
+        palette: &Palette,
+        scale: usize,
+    ) -> Self {
+        let scale = scale.max(1);
+        let (width, height) = if chip8.platform().supports_superchip() {
+            (128, 64)
+        } else {
+            chip8.display_size()
+        };
+
+        let mut recording = Recording {
+            output: Box::new(output),
+            format,
+            palette: *palette,
+            width: width * scale,
+            height: height * scale,
+            frames: 0,
+            pending: None,
+            pending_start: 0,
+            error: None,
+        };
+        if format == RecordingFormat::Gif {
+            let header = recording.gif_header();
+            recording.write(&header);
+        }
+        recording
+    }
+
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: RecordingFormat,
+        chip8: &Chip8,
+        palette: &Palette,
+        scale: usize,
+    ) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Recording::new(
+            BufWriter::new(file),
+            format,
+            chip8,
+            palette,
+            scale,
+        ))
+    }
+
+    /// Size of the recorded frames, in pixels
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Number of frames recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Adds the current display as the next frame, to be called after each
+    /// Chip8::run_frame()
+    pub fn record_frame(&mut self, chip8: &Chip8) {
+        let frame = Screenshot::capture_resized(chip8, &self.palette, self.width, self.height);
+
+        match self.format {
+            RecordingFormat::Raw => {
+                let rgb: Vec<u8> = frame
+                    .pixels()
+                    .iter()
+                    .flat_map(|&pixel| {
+                        let (r, g, b) = self.palette.color(pixel);
+                        vec![r, g, b]
+                    })
+                    .collect();
+                self.write(&rgb);
+            }
+            RecordingFormat::Gif => {
+                if self.pending.as_ref() != Some(&frame) {
+                    self.write_pending_frame();
+                    self.pending = Some(frame);
+                    self.pending_start = self.frames;
+                }
+            }
+        }
+        self.frames += 1;
+    }
+
+    /// Writes the last frames and the end of the file. Returns the first write error
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == RecordingFormat::Gif {
+            self.write_pending_frame();
+            self.write(&[0x3B]);
+        }
+
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.output.flush()
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if self.error.is_none() {
+            if let Err(error) = self.output.write_all(data) {
+                self.error = Some(error);
+            }
+        }
+    }
+
+    /// Logical screen, global colour table and looping extension
+    fn gif_header(&self) -> Vec<u8> {
+        let mut header = b"GIF89a".to_vec();
+        header.extend_from_slice(&(self.width as u16).to_le_bytes());
+        header.extend_from_slice(&(self.height as u16).to_le_bytes());
+        // Global colour table of 2 ^ GIF_COLOR_BITS colours, 8 bits per primary colour
+        header.extend_from_slice(&[0xF0 | (GIF_COLOR_BITS - 1), 0, 0]);
+        for &(r, g, b) in self.palette.colors.iter() {
+            header.extend_from_slice(&[r, g, b]);
+        }
+
+        // Loops forever
+        header.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        header.extend_from_slice(b"NETSCAPE2.0");
+        header.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+        header
+    }
+
+    /// Writes the pending frame, lasting until the current one
+    ///
+    /// GIF delays are in hundredths of a second, so they alternate between 1 and 2 to
+    /// average 60 Hz. Some viewers slow down the shortest ones, the raw format doesn't
+    /// have that issue
+    fn write_pending_frame(&mut self) {
+        let frame = match self.pending.take() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let centiseconds = |frames: u64| frames * 100 / FRAME_RATE as u64;
+        let delay = centiseconds(self.frames) - centiseconds(self.pending_start);
+
+        // Graphic control extension, then the image descriptor covering the whole screen
+        let mut data = vec![0x21, 0xF9, 0x04, 0x00];
+        data.extend_from_slice(&(delay.min(u16::MAX as u64) as u16).to_le_bytes());
+        data.extend_from_slice(&[0x00, 0x00, 0x2C, 0, 0, 0, 0]);
+        data.extend_from_slice(&(self.width as u16).to_le_bytes());
+        data.extend_from_slice(&(self.height as u16).to_le_bytes());
+        data.push(0x00);
+
+        data.push(GIF_COLOR_BITS);
+        for block in lzw(frame.pixels(), GIF_COLOR_BITS).chunks(255) {
+            data.push(block.len() as u8);
+            data.extend_from_slice(block);
+        }
+        data.push(0x00);
+        self.write(&data);
+    }
+}
+
+/// Compresses pixels with the variable code length LZW of GIF images
+fn lzw(pixels: &[u8], minimum_size: u8) -> Vec<u8> {
+    let clear = 1u16 << minimum_size;
+    let end = clear + 1;
+    let mut bits = BitWriter::default();
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = minimum_size + 1;
+    bits.write(clear as u32, size);
+
+    let mut prefix = match pixels.first() {
+        Some(&pixel) => pixel as u16,
+        None => {
+            bits.write(end as u32, size);
+            return bits.finish();
+        }
+    };
+    for &pixel in &pixels[1..] {
+        if let Some(&code) = codes.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+
+        bits.write(prefix as u32, size);
+        if next < MAX_LZW_CODE {
+            codes.insert((prefix, pixel), next);
+            next += 1;
+            // Decoders add their codes one step later, and widen them once the next one
+            // doesn't fit anymore
+            if next > 1 << size {
+                size += 1;
+            }
+        } else {
+            bits.write(clear as u32, size);
+            codes.clear();
+            next = end + 1;
+            size = minimum_size + 1;
+        }
+        prefix = pixel as u16;
+    }
+
+    bits.write(prefix as u32, size);
+    // The decoder adds a code after reading the last one too
+    if next == 1 << size && next < MAX_LZW_CODE {
+        size += 1;
+    }
+    bits.write(end as u32, size);
+    bits.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Platform;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Writer whose content stays readable after the Recording took it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Decodes GIF LZW codes back into pixels
+    fn unlzw(data: &[u8], minimum_size: u8) -> Vec<u8> {
+        let clear = 1usize << minimum_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = minimum_size + 1;
+        let mut position = 0;
+        let mut previous: Option<usize> = None;
+        let mut pixels = Vec::new();
+
+        loop {
+            let code = (0..size as usize).fold(0, |code, bit| {
+                let index = position + bit;
+                code | (((data[index / 8] >> (index % 8)) & 1) as usize) << bit
+            });
+            position += size as usize;
+
+            if code == clear {
+                table = (0..clear as u8).map(|pixel| vec![pixel]).collect();
+                table.extend_from_slice(&[Vec::new(), Vec::new()]);
+                size = minimum_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return pixels;
+            }
+
+            let entry = match (table.get(code), previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => {
+                    let mut entry = table[previous].clone();
+                    entry.push(table[previous][0]);
+                    entry
+                }
+                (None, None) => panic!("invalid code {}", code),
+            };
+            if let Some(previous) = previous {
+                if table.len() < MAX_LZW_CODE as usize {
+                    let mut added = table[previous].clone();
+                    added.push(entry[0]);
+                    table.push(added);
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                }
+            }
+            pixels.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+    }
+
+    #[test]
+    fn test_lzw() {
+        let mut seed = 12345u32;
+        let noise: Vec<u8> = (0..20000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8 & 3
+            })
+            .collect();
+        let runs: Vec<u8> = (0..20000).map(|i| (i / 300 % 4) as u8).collect();
+
+        for pixels in [&noise[..], &runs[..], &[], &[1], &[0, 0, 0, 0]] {
+            assert_eq!(unlzw(&lzw(pixels, 2), 2), pixels);
+        }
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD I, 0x204 / 0x202: DRW V0, V0, 1 / 0x204: sprite
+        chip8
+            .load_program_bytes(&[0xA2, 0x04, 0xD0, 0x01, 0xFF])
+            .unwrap();
+        let buffer = SharedBuffer::default();
+        let mut recording = Recording::new(
+            buffer.clone(),
+            RecordingFormat::Gif,
+            &chip8,
+            &Palette::default(),
+            1,
+        );
+
+        recording.record_frame(&chip8);
+        recording.record_frame(&chip8);
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        recording.record_frame(&chip8);
+        assert_eq!(recording.frames(), 3);
+        recording.finish().unwrap();
+
+        let gif = buffer.0.borrow();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(&gif[6..10], &[64, 0, 32, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+
+        // 2 images, the first one lasting 2/60 s and the second one 1/60 s
+        let delays: Vec<u16> = gif
+            .windows(6)
+            .filter(|window| window[..3] == [0x21, 0xF9, 0x04])
+            .map(|window| u16::from_le_bytes([window[4], window[5]]))
+            .collect();
+        assert_eq!(delays, [3, 2]);
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut chip8 = Chip8::with_platform(Platform::SuperChip);
+        chip8
+            .load_program_bytes(&[0xA2, 0x04, 0xD0, 0x01, 0xFF])
+            .unwrap();
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        let buffer = SharedBuffer::default();
+        let mut recording = Recording::new(
+            buffer.clone(),
+            RecordingFormat::Raw,
+            &chip8,
+            &Palette::default(),
+            1,
+        );
+
+        assert_eq!(recording.size(), (128, 64));
+        recording.record_frame(&chip8);
+        recording.finish().unwrap();
+
+        let raw = buffer.0.borrow();
+        assert_eq!(raw.len(), 128 * 64 * 3);
+        // The lores pixels are doubled
+        assert_eq!(&raw[..9], &[255, 255, 255, 255, 255, 255, 255, 255, 255]);
+        assert_eq!(&raw[128 * 3..128 * 3 + 6], &[255; 6]);
+    }
+}
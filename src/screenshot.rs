@@ -0,0 +1,308 @@
+use crate::palette::Palette;
+use crate::Chip8;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Image of the display of a Chip8, each pixel scaled up to a square of scale x scale
+///
+/// Pixels keep the display values (0-3), looked up in the palette when encoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    palette: Palette,
+}
+
+impl Screenshot {
+    /// Captures the current display. A scale of 0 is treated as 1
+    pub fn capture(chip8: &Chip8, palette: &Palette, scale: usize) -> Self {
+        let scale = scale.max(1);
+        let (width, height) = chip8.display_size();
+        Self::capture_resized(chip8, palette, width * scale, height * scale)
+    }
+
+    /// Captures the current display stretched to width x height, whatever its resolution
+    pub fn capture_resized(chip8: &Chip8, palette: &Palette, width: usize, height: usize) -> Self {
+        let (display_width, display_height) = chip8.display_size();
+        let display = chip8.display();
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = &display[y * display_height / height * display_width..][..display_width];
+            for x in 0..width {
+                pixels.push(row[x * display_width / width].min(3));
+            }
+        }
+
+        Screenshot {
+            width,
+            height,
+            pixels,
+            palette: *palette,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Display value of each pixel, row-major
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Encodes the image as an indexed colour PNG
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bits per pixel, indexed colour, then the only compression, filter and
+        // interlace methods PNG defines
+        header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+        let colors: Vec<u8> = self
+            .palette
+            .colors
+            .iter()
+            .flat_map(|&(r, g, b)| vec![r, g, b])
+            .collect();
+
+        // Each scanline starts with its filter type, 0 meaning unfiltered
+        let mut scanlines = Vec::with_capacity((self.width + 1) * self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"PLTE", &colors);
+        write_chunk(&mut png, b"IDAT", &zlib(&scanlines, self.width + 1));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_png())
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Compresses data in the zlib format, row being the length of the image rows in data
+fn zlib(data: &[u8], row: usize) -> Vec<u8> {
+    // Deflate with a 32K window, no preset dictionary
+    let mut output = vec![0x78, 0x01];
+    output.append(&mut deflate(data, row));
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    output
+}
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const MAX_MATCH: usize = 258;
+const WINDOW_SIZE: usize = 32768;
+
+/// Deflates data in a single block with the fixed Huffman codes
+///
+/// Screenshots are made of runs of the same pixel and rows repeated by the scaling, so
+/// matches are only looked for one byte and one row back
+fn deflate(data: &[u8], row: usize) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // Final block, fixed Huffman codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = [row, 1]
+            .iter()
+            .filter(|&&distance| distance > 0 && distance <= position.min(WINDOW_SIZE))
+            .map(|&distance| {
+                let length = (0..MAX_MATCH.min(data.len() - position))
+                    .take_while(|&i| data[position + i] == data[position + i - distance])
+                    .count();
+                (length, distance)
+            })
+            .max()
+            .unwrap_or((0, 0));
+
+        if length >= 3 {
+            let code = LENGTH_BASES
+                .iter()
+                .rposition(|&base| base as usize <= length)
+                .unwrap();
+            bits.write_symbol(257 + code as u16);
+            bits.write(
+                (length - LENGTH_BASES[code] as usize) as u32,
+                LENGTH_EXTRA_BITS[code],
+            );
+
+            let code = DISTANCE_BASES
+                .iter()
+                .rposition(|&base| base as usize <= distance)
+                .unwrap();
+            bits.write_reversed(code as u32, 5);
+            bits.write(
+                (distance - DISTANCE_BASES[code] as usize) as u32,
+                DISTANCE_EXTRA_BITS[code],
+            );
+            position += length;
+        } else {
+            bits.write_symbol(data[position] as u16);
+            position += 1;
+        }
+    }
+
+    bits.write_symbol(256);
+    bits.finish()
+}
+
+/// Packs bits starting from the least significant bit of each byte, as deflate does
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    pub(crate) fn write(&mut self, value: u32, count: u8) {
+        for bit in 0..count {
+            self.current |= ((value >> bit) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.current as u8);
+                self.current = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Writes a Huffman code, which goes most significant bit first
+    fn write_reversed(&mut self, code: u32, count: u8) {
+        for bit in (0..count).rev() {
+            self.write(code >> bit, 1);
+        }
+    }
+
+    /// Writes a literal/length symbol with its fixed Huffman code
+    fn write_symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_reversed(0x30 + symbol, 8),
+            144..=255 => self.write_reversed(0x190 + symbol - 144, 9),
+            256..=279 => self.write_reversed(symbol - 256, 7),
+            _ => self.write_reversed(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD I, 0x206 / 0x202: DRW V0, V0, 1 / 0x204: JP 0x204 / 0x206: sprite
+        chip8
+            .load_program_bytes(&[0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0xA0])
+            .unwrap();
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+
+        let screenshot = Screenshot::capture(&chip8, &Palette::default(), 2);
+        assert_eq!((screenshot.width(), screenshot.height()), (128, 64));
+        assert_eq!(&screenshot.pixels()[..6], &[1, 1, 0, 0, 1, 1]);
+        assert_eq!(&screenshot.pixels()[128..134], &[1, 1, 0, 0, 1, 1]);
+        assert_eq!(screenshot.pixels()[256], 0);
+
+        let screenshot = Screenshot::capture_resized(&chip8, &Palette::default(), 32, 16);
+        assert_eq!(&screenshot.pixels()[..3], &[1, 1, 0]);
+    }
+
+    #[test]
+    fn test_png() {
+        let chip8 = Chip8::new();
+        let png = Screenshot::capture(&chip8, &Palette::default(), 1).to_png();
+
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 64, 0, 0, 0, 32]);
+        assert_eq!(crc32(&png[12..29]).to_be_bytes(), png[29..33]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // An empty display compresses to a few bytes
+        assert!(png.len() < 128);
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::chip8::FRAME_RATE;
+use crate::Chip8;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Pitch of the square wave, in Hz
+pub const DEFAULT_FREQUENCY: f64 = 440.0;
+
+/// Amplitude of the samples, from 0 (muted) to 1
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+/// Bits in an XO-CHIP audio pattern
+const PATTERN_BITS: f64 = 128.0;
+
+/// Generates the buzzer samples of a Chip8, for frontends to send to their audio output
+///
+/// A square wave sounds while the sound timer is non-zero. XO-CHIP programs that loaded an
+/// audio pattern hear that pattern instead, at the rate set by their pitch register
+#[derive(Debug, Clone)]
+pub struct Beeper {
+    sample_rate: u32,
+    frequency: f64,
+    volume: f32,
+    playing: bool,
+    /// Pattern and the rate of its bits, in bits per second
+    pattern: Option<([u8; 16], f64)>,
+    /// Position in the square wave period or the pattern, from 0 to 1
+    phase: f64,
+}
+
+impl Beeper {
+    /// Creates a silent Beeper producing sample_rate samples per second
+    pub fn new(sample_rate: u32) -> Self {
+        Beeper {
+            sample_rate,
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            playing: false,
+            pattern: None,
+            phase: 0.0,
+        }
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f64) {
+        self.frequency = frequency;
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Sets the amplitude, clamped between 0 and 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Follows the sound timer and XO-CHIP audio pattern of chip8, to be called once per
+    /// frame
+    ///
+    /// A paused machine is silent, since its sound timer doesn't count down
+    pub fn update(&mut self, chip8: &Chip8) {
+        self.playing = chip8.is_sound_on() && !chip8.is_paused();
+        self.pattern = chip8
+            .audio_pattern()
+            .map(|pattern| (*pattern, chip8.audio_playback_rate()));
+    }
+
+    /// Fills samples with the next ones, silence when not playing
+    pub fn fill(&mut self, samples: &mut [f32]) {
+        if !self.playing {
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+            self.phase = 0.0;
+            return;
+        }
+
+        let step = match self.pattern {
+            Some((_, rate)) => rate / PATTERN_BITS,
+            None => self.frequency,
+        } / self.sample_rate as f64;
+
+        for sample in samples.iter_mut() {
+            let high = match &self.pattern {
+                Some((pattern, _)) => {
+                    let bit = (self.phase * PATTERN_BITS) as usize;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => self.phase < 0.5,
+            };
+            *sample = if high { self.volume } else { -self.volume };
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+/// Renders the buzzer of a Chip8 frame by frame into a PCM sample stream, for offline
+/// uses like regression tests, which can then save it as a WAV file
+#[derive(Debug, Clone)]
+pub struct SoundRecorder {
+    beeper: Beeper,
+    sample_rate: u32,
+    frames: u64,
+    samples: Vec<f32>,
+}
+
+impl SoundRecorder {
+    pub fn new(sample_rate: u32) -> Self {
+        SoundRecorder {
+            beeper: Beeper::new(sample_rate),
+            sample_rate,
+            frames: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Generator of the samples, to change its frequency and volume
+    pub fn beeper_mut(&mut self) -> &mut Beeper {
+        &mut self.beeper
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Samples recorded so far, between -1 and 1
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Appends the samples of one 60 Hz frame, to be called after each Chip8::run_frame()
+    pub fn record_frame(&mut self, chip8: &Chip8) {
+        self.beeper.update(chip8);
+
+        // Frames don't always last a whole number of samples, so their length is
+        // rounded down from the start of the recording rather than from the previous one
+        self.frames += 1;
+        let end = self.frames * self.sample_rate as u64 / FRAME_RATE as u64;
+        let start = self.samples.len();
+        self.samples.resize(end as usize, 0.0);
+        self.beeper.fill(&mut self.samples[start..]);
+    }
+
+    /// Writes the samples as a mono 16-bit PCM WAV file
+    pub fn write_wav<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let data_size = self.samples.len() as u32 * 2;
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(36 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM format, 1 channel
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        // Bytes per second, bytes per sample and bits per sample
+        header.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+        writer.write_all(&header)?;
+
+        for &sample in &self.samples {
+            let value = (sample * i16::MAX as f32) as i16;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn save_wav<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_wav(BufWriter::new(File::create(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Platform;
+
+    #[test]
+    fn test_square_wave() {
+        let mut chip8 = Chip8::new();
+        let mut beeper = Beeper::new(8000);
+        beeper.set_frequency(1000.0);
+        beeper.set_volume(2.0);
+        let mut samples = [1.0; 8];
+
+        beeper.update(&chip8);
+        beeper.fill(&mut samples);
+        assert_eq!(samples, [0.0; 8]);
+
+        chip8.set_sound_timer(2);
+        beeper.update(&chip8);
+        beeper.fill(&mut samples);
+        assert_eq!(samples, [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+
+        chip8.pause();
+        beeper.update(&chip8);
+        assert!(!beeper.is_playing());
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD I, 0x206 / 0x202: F002 (audio) / 0x204: LD ST, V0 / 0x206: pattern
+        let mut program = vec![0xA2, 0x06, 0xF0, 0x02, 0xF0, 0x18];
+        program.extend_from_slice(&[0xA0; 16]);
+        chip8.set_platform(Platform::XoChip);
+        chip8.load_program_bytes(&program).unwrap();
+        chip8.set_register(0, 10);
+        for _ in 0..3 {
+            chip8.emulate_cycle().unwrap();
+        }
+
+        // 4000 bits per second played at 4000 samples per second, one bit per sample
+        let mut beeper = Beeper::new(4000);
+        beeper.set_volume(0.5);
+        beeper.update(&chip8);
+        let mut samples = [0.0; 4];
+        beeper.fill(&mut samples);
+        assert_eq!(samples, [0.5, -0.5, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD V0, 2 / 0x202: LD ST, V0 / 0x204: JP 0x204
+        chip8
+            .load_program_bytes(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04])
+            .unwrap();
+        let mut recorder = SoundRecorder::new(100);
+        recorder.beeper_mut().set_frequency(25.0);
+        recorder.beeper_mut().set_volume(1.0);
+
+        // Frames last 1.67 samples, the sound timer keeps the buzzer on for 2 of them
+        for _ in 0..4 {
+            chip8.run_frame().unwrap();
+            recorder.record_frame(&chip8);
+        }
+        assert_eq!(recorder.samples(), [1.0, 1.0, -1.0, 0.0, 0.0, 0.0]);
+
+        let mut wav = Vec::new();
+        recorder.write_wav(&mut wav).unwrap();
+        assert_eq!(wav.len(), 44 + 12);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[4..8], &48u32.to_le_bytes());
+        assert_eq!(&wav[24..28], &100u32.to_le_bytes());
+        assert_eq!(&wav[40..44], &12u32.to_le_bytes());
+        assert_eq!(&wav[44..48], &[0xFF, 0x7F, 0xFF, 0x7F]);
+    }
+}